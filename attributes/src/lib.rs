@@ -1,80 +1,311 @@
 use proc_macro::TokenStream;
-use std::ops::Deref;
 use quote::quote;
-use syn::{FnArg, ItemFn, parse, ReturnType, Signature, Type};
+use syn::{Error, Ident, ItemFn, parse_quote};
 use syn::__private::TokenStream2;
+use crate::options::Options;
+use crate::signature::{attribute_signature, derive_signature, fn_signature, MacroKind, OutputKind, type_has_name};
+
+mod options;
+mod signature;
 
 /// Creates testable code for attributes written with syn:TokenStream2. See README.md for further information.
+///
+/// Supported options are 'module' (the name of the implementation module), 'vis' (the visibility of the
+/// implementation module), 'name' (the name of the macro, if it should differ from the function name) and
+/// 'catch_panics' (turn panics of the implementation into compile errors at the annotated item),
+/// e.g. '#[proc_macro_attribute2(module = imp, vis = pub(crate), name = "derive_answer", catch_panics)]'.
 #[proc_macro_attribute]
-pub fn proc_macro_attribute2(_attributes: TokenStream, item: TokenStream) -> TokenStream {
-    let item_func = parse::<ItemFn>(item).expect("'proc_macro_attribute2' is only allowed on functions");
-    implement(item_func).into()
+pub fn proc_macro_attribute2(attributes: TokenStream, item: TokenStream) -> TokenStream {
+    syn::parse::<Options>(attributes)
+        .and_then(|options| parse_function(item, &signature::ATTRIBUTE).map(|item_func| (options, item_func)))
+        .and_then(|(options, item_func)| implement(&options, item_func))
+        .unwrap_or_else(|error| error.to_compile_error())
+        .into()
 }
 
-fn implement(item_func: ItemFn) -> TokenStream2 {
-    if !signature_as_expected(&item_func.sig) {
-        panic!("'testable_proc_macro_attribute' is only applicable on functions of type (AttributeArgs, TokenStream2) -> TokenStream2")
-    }
+fn implement(options: &Options, item_func: ItemFn) -> syn::Result<TokenStream2> {
+    let (attributes_type, item_type, output_kind) = attribute_signature(&item_func.sig)?;
+
+    let ident = macro_name(options, &item_func);
+    let module = module_name(options, &item_func);
+    let implementation_module = implementation_module(options, &item_func);
+    let typed_item = !type_has_name(item_type, "TokenStream2");
+    let keep_original_item = typed_item || matches!(output_kind, OutputKind::Result);
+
+    // copied before the item is parsed, as it is emitted next to errors (see 'error_output')
+    let original_item = match keep_original_item {
+        true => quote! { let original_item = ::core::clone::Clone::clone(&item); },
+        false => quote! {}
+    };
+    let parse_item = match typed_item {
+        true => {
+            let parse_error = error_output(true);
+            quote! {
+                let item = match ::macro_test_runtime::parse_item::<#item_type>(::core::convert::Into::into(item)) {
+                    ::core::result::Result::Ok(item) => item,
+                    ::core::result::Result::Err(error) => return #parse_error
+                };
+            }
+        }
+        false => quote! { let item = ::core::convert::Into::into(item); }
+    };
+    let output = convert_output(output_kind, keep_original_item, quote! { #module::implementation(attributes, item) });
+
+    let body = record_invocations(&ident, catch_panics(options, &ident, quote! { item }, true, quote! {
+        #original_item
+        let attributes = ::macro_test_runtime::syn::parse_macro_input!(attributes as #attributes_type);
+        #parse_item
+        #output
+    }));
+
+    Ok(quote! {
+        #[proc_macro_attribute]
+        pub fn #ident (attributes: ::proc_macro::TokenStream, item: ::proc_macro::TokenStream) -> ::proc_macro::TokenStream {
+            #body
+        }
+
+        #implementation_module
+    })
+}
+
+/// Creates testable code for derive macros written with syn:TokenStream2. The arguments are the same
+/// as for 'proc_macro_derive', e.g. '#[proc_macro_derive2(Answer, attributes(answer))]'.
+/// See README.md for further information.
+#[proc_macro_attribute]
+pub fn proc_macro_derive2(attributes: TokenStream, item: TokenStream) -> TokenStream {
+    parse_function(item, &signature::DERIVE)
+        .and_then(|item_func| implement_derive(attributes.into(), item_func))
+        .unwrap_or_else(|error| error.to_compile_error())
+        .into()
+}
+
+fn implement_derive(derive_args: TokenStream2, item_func: ItemFn) -> syn::Result<TokenStream2> {
+    let output_kind = derive_signature(&item_func.sig)?;
 
     let ident = &item_func.sig.ident;
+    let implementation_module = implementation_module(&Options::default(), &item_func);
+    let body = convert_output(output_kind, false, quote! {
+        #ident::implementation(
+            ::macro_test_runtime::syn::parse_macro_input!(item as ::macro_test_runtime::syn::DeriveInput)
+        )
+    });
+
+    Ok(quote! {
+        #[proc_macro_derive(#derive_args)]
+        pub fn #ident (item: ::proc_macro::TokenStream) -> ::proc_macro::TokenStream {
+            #body
+        }
+
+        #implementation_module
+    })
+}
+
+/// Creates testable code for function-like macros written with syn:TokenStream2. The annotated function
+/// takes the macro input either as TokenStream2 or as any type implementing syn::parse::Parse.
+/// Supports the same options as 'proc_macro_attribute2'. See README.md for further information.
+#[proc_macro_attribute]
+pub fn proc_macro_fn2(attributes: TokenStream, item: TokenStream) -> TokenStream {
+    syn::parse::<Options>(attributes)
+        .and_then(|options| parse_function(item, &signature::FUNCTION).map(|item_func| (options, item_func)))
+        .and_then(|(options, item_func)| implement_fn(&options, item_func))
+        .unwrap_or_else(|error| error.to_compile_error())
+        .into()
+}
+
+fn implement_fn(options: &Options, item_func: ItemFn) -> syn::Result<TokenStream2> {
+    let (input_type, output_kind) = fn_signature(&item_func.sig)?;
+
+    let ident = macro_name(options, &item_func);
+    let module = module_name(options, &item_func);
+    let implementation_module = implementation_module(options, &item_func);
+    let body = catch_panics(options, &ident, quote! { input }, false, convert_output(output_kind, false, quote! {
+        #module::implementation(
+            ::macro_test_runtime::syn::parse_macro_input!(input as #input_type)
+        )
+    }));
+
+    Ok(quote! {
+        #[proc_macro]
+        pub fn #ident (input: ::proc_macro::TokenStream) -> ::proc_macro::TokenStream {
+            #body
+        }
+
+        #implementation_module
+    })
+}
+
+/// Runs the body of the macro function under catch_unwind if the option 'catch_panics' is set. rustc only
+/// reports "proc macro panicked" for a panic, so it is turned into a compile error naming the macro and
+/// spanning the input instead. Attribute macros emit their input after the error, like for returned errors.
+fn catch_panics(options: &Options, macro_name: &Ident, input: TokenStream2, emit_input: bool, body: TokenStream2) -> TokenStream2 {
+    if !options.catch_panics {
+        return body;
+    }
+
+    let macro_name = macro_name.to_string();
+    let emitted_input = match emit_input {
+        true => quote! { ::core::iter::Extend::extend(&mut implementation, original_input); },
+        false => quote! {}
+    };
+
+    quote! {
+        let original_input = ::core::clone::Clone::clone(&#input);
+        let result = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(move || -> ::proc_macro::TokenStream {
+            #body
+        }));
+
+        match result {
+            ::core::result::Result::Ok(implementation) => implementation,
+            ::core::result::Result::Err(payload) => {
+                let input = ::core::convert::Into::into(::core::clone::Clone::clone(&original_input));
+                let mut implementation: ::proc_macro::TokenStream = ::core::convert::Into::into(
+                    ::macro_test_runtime::panic_to_compile_error(#macro_name, payload, input)
+                );
+                #emitted_input
+                implementation
+            }
+        }
+    }
+}
+
+/// Records the arguments, the item and the output of the attribute macro into a corpus if MACRO_TEST_RECORD
+/// is set. The recorded invocations can be replayed as regression tests with 'macro_test::replay_corpus'.
+fn record_invocations(macro_name: &Ident, body: TokenStream2) -> TokenStream2 {
+    let macro_name = macro_name.to_string();
+
+    quote! {
+        let recorded_input = match ::macro_test_runtime::recording_enabled() {
+            true => ::core::option::Option::Some((::core::clone::Clone::clone(&attributes), ::core::clone::Clone::clone(&item))),
+            false => ::core::option::Option::None
+        };
+        let implementation = (move || -> ::proc_macro::TokenStream {
+            #body
+        })();
+
+        if let ::core::option::Option::Some((attributes, item)) = recorded_input {
+            ::macro_test_runtime::record_invocation(
+                #macro_name,
+                ::core::convert::Into::into(attributes),
+                ::core::convert::Into::into(item),
+                ::core::convert::Into::into(::core::clone::Clone::clone(&implementation))
+            );
+        }
+        implementation
+    }
+}
+
+/// Creates the module containing the testable implementation. Every macro gets its own module, by default
+/// named like the macro, so a crate can contain any number of them. Modules and functions live in different
+/// namespaces, so the module does not collide with the generated macro function.
+fn implementation_module(options: &Options, item_func: &ItemFn) -> TokenStream2 {
+    let module = module_name(options, item_func);
+    let vis = options.vis.clone().unwrap_or_else(|| parse_quote! { pub (in crate) });
     let block = &item_func.block;
     let params = &item_func.sig.inputs;
     let output = &item_func.sig.output;
 
     quote! {
-        #[proc_macro_attribute]
-        pub fn #ident (attributes: proc_macro::TokenStream, item: proc_macro::TokenStream) -> proc_macro::TokenStream {
-            implementation::#ident(
-                syn::parse_macro_input!(attributes as AttributeArgs),
-                item.into()
-            ).into()
-        }
-
-        pub (in crate) mod implementation {
+        #vis mod #module {
             use super::*;
 
-            pub fn #ident (#params) #output {
+            pub fn implementation (#params) #output {
                 #block
             }
         }
     }
 }
 
-fn signature_as_expected(sig: &Signature) -> bool {
-    if sig.inputs.len() != 2 {
-        return false;
-    }
+fn macro_name(options: &Options, item_func: &ItemFn) -> Ident {
+    options.name.clone().unwrap_or_else(|| item_func.sig.ident.clone())
+}
+
+fn module_name(options: &Options, item_func: &ItemFn) -> Ident {
+    options.module.clone().unwrap_or_else(|| macro_name(options, item_func))
+}
 
-    let first_param_ok = argument_of_expected_type(&sig.inputs[0], "AttributeArgs");
-    let second_param_ok = argument_of_expected_type(&sig.inputs[1], "TokenStream2");
-    let output_ok = output_of_expected_type(&sig.output);
-    first_param_ok && second_param_ok && output_ok
+fn parse_function(item: TokenStream, macro_kind: &MacroKind) -> syn::Result<ItemFn> {
+    let item = TokenStream2::from(item);
+    syn::parse2::<ItemFn>(item.clone())
+        .map_err(|_| Error::new_spanned(item, format!("'{}' is only allowed on functions", macro_kind.name)))
 }
 
-fn argument_of_expected_type(input: &FnArg, expected_type_name: &str) -> bool {
-    match input {
-        FnArg::Typed(typed) => match typed.ty.deref() {
-            Type::Path(p) => p.path.segments
-                .last()
-                .map(|seg| &seg.ident)
-                .map(|ident| ident.to_string() == expected_type_name)
-                .unwrap_or(false),
-            _ => false
+/// Turns the output of the implementation call into a proc_macro::TokenStream. Errors become compile errors,
+/// see 'error_output'.
+fn convert_output(output_kind: OutputKind, emit_original_item: bool, call: TokenStream2) -> TokenStream2 {
+    match output_kind {
+        OutputKind::TokenStream => quote! { ::core::convert::Into::into(#call) },
+        OutputKind::Result => {
+            let error = error_output(emit_original_item);
+            quote! {
+                match #call {
+                    ::core::result::Result::Ok(implementation) => ::core::convert::Into::into(implementation),
+                    ::core::result::Result::Err(error) => #error
+                }
+            }
         }
-        _ => false,
     }
 }
 
-fn output_of_expected_type(output: &ReturnType) -> bool {
-    match output {
-        ReturnType::Type(_, ty) => match ty.deref() {
-            Type::Path(p) => p.path.segments
-                .last()
-                .map(|seg| &seg.ident)
-                .map(|ident| ident.to_string() == "TokenStream2")
-                .unwrap_or(false),
-            _ => false,
-        }
-        _ => false,
+/// Turns the syn::Error 'error' into the output of the macro. Attribute macros emit the original item after
+/// the compile error, so the compiler does not report every usage of the item as an additional error.
+fn error_output(emit_original_item: bool) -> TokenStream2 {
+    match emit_original_item {
+        true => quote! {{
+            let mut implementation: ::proc_macro::TokenStream = ::core::convert::Into::into(error.to_compile_error());
+            ::core::iter::Extend::extend(&mut implementation, original_item);
+            implementation
+        }},
+        false => quote! { ::core::convert::Into::into(error.to_compile_error()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::quote;
+    use syn::parse_quote;
+    use syn::__private::TokenStream2;
+    use crate::{implement, implement_fn};
+    use crate::options::Options;
+
+    fn contains(expansion: &str, tokens: TokenStream2) -> bool {
+        expansion.contains(&tokens.to_string())
+    }
+
+    #[test]
+    fn panics_of_attributes_are_caught_with_the_option() {
+        let options: Options = parse_quote! { catch_panics };
+        let expansion = implement(&options, parse_quote! {
+            fn answer(args: AttributeArgs, item: TokenStream2) -> TokenStream2 { item }
+        }).unwrap().to_string();
+
+        assert!(contains(&expansion, quote! { ::std::panic::catch_unwind }), "{}", expansion);
+        assert!(contains(&expansion, quote! { ::macro_test_runtime::panic_to_compile_error("answer", payload, input) }), "{}", expansion);
+        assert!(contains(&expansion, quote! { ::core::iter::Extend::extend(&mut implementation, original_input); }), "{}", expansion);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn panics_of_attributes_are_not_caught_without_the_option() {
+        let expansion = implement(&Options::default(), parse_quote! {
+            fn answer(args: AttributeArgs, item: TokenStream2) -> TokenStream2 { item }
+        }).unwrap().to_string();
+
+        assert!(!expansion.contains("catch_unwind"), "{}", expansion);
+        assert!(!expansion.contains("panic_to_compile_error"), "{}", expansion);
+    }
+
+    #[test]
+    fn panics_of_function_like_macros_are_caught_without_emitting_the_input() {
+        let options: Options = parse_quote! { catch_panics };
+        let expansion = implement_fn(&options, parse_quote! {
+            fn answer(input: TokenStream2) -> TokenStream2 { input }
+        }).unwrap().to_string();
+
+        assert!(contains(&expansion, quote! { ::macro_test_runtime::panic_to_compile_error("answer", payload, input) }), "{}", expansion);
+        assert!(!contains(&expansion, quote! { ::core::iter::Extend::extend }), "{}", expansion);
+
+        let expansion = implement_fn(&Options::default(), parse_quote! {
+            fn answer(input: TokenStream2) -> TokenStream2 { input }
+        }).unwrap().to_string();
+        assert!(!expansion.contains("catch_unwind"), "{}", expansion);
+    }
+}
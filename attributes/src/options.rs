@@ -0,0 +1,107 @@
+use syn::{Error, Ident, LitStr, Token, Visibility};
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+
+const OPTION_NAMES: &str = "'module', 'vis', 'name' and 'catch_panics'";
+
+/// The options of 'proc_macro_attribute2' and 'proc_macro_fn2', e.g.
+/// '#[proc_macro_attribute2(module = imp, vis = pub(crate), name = "derive_answer", catch_panics)]'.
+#[derive(Default)]
+pub struct Options {
+    /// The name of the module containing the implementation. Defaults to the name of the macro.
+    pub module: Option<Ident>,
+    /// The visibility of the module containing the implementation. Defaults to 'pub (in crate)'.
+    pub vis: Option<Visibility>,
+    /// The name of the exported macro. Defaults to the name of the annotated function.
+    pub name: Option<Ident>,
+    /// Whether panics of the implementation are turned into compile errors. Off by default.
+    pub catch_panics: bool,
+}
+
+impl Parse for Options {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut options = Options::default();
+
+        for option in Punctuated::<MacroOption, Token![,]>::parse_terminated(input)? {
+            let key = option.key().clone();
+            let already_set = match option {
+                MacroOption::Module(_, module) => options.module.replace(module).is_some(),
+                MacroOption::Vis(_, vis) => options.vis.replace(vis).is_some(),
+                MacroOption::Name(_, name) => options.name.replace(name).is_some(),
+                MacroOption::CatchPanics(_) => std::mem::replace(&mut options.catch_panics, true),
+            };
+
+            if already_set {
+                return Err(Error::new(key.span(), format!("the option '{}' is set more than once", key)));
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+enum MacroOption {
+    Module(Ident, Ident),
+    Vis(Ident, Visibility),
+    Name(Ident, Ident),
+    CatchPanics(Ident),
+}
+
+impl MacroOption {
+    fn key(&self) -> &Ident {
+        match self {
+            MacroOption::Module(key, _) | MacroOption::Vis(key, _) | MacroOption::Name(key, _) | MacroOption::CatchPanics(key) => key
+        }
+    }
+}
+
+impl Parse for MacroOption {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let key = input.parse::<Ident>()?;
+
+        // a flag without value
+        if key == "catch_panics" {
+            return Ok(MacroOption::CatchPanics(key));
+        }
+
+        input.parse::<Token![=]>()?;
+
+        if key == "module" {
+            Ok(MacroOption::Module(key, input.parse()?))
+        } else if key == "vis" {
+            Ok(MacroOption::Vis(key, input.parse()?))
+        } else if key == "name" {
+            // the name can be given as string, like the 'name' of 'proc_macro_derive', or as identifier
+            let name = match input.peek(LitStr) {
+                true => input.parse::<LitStr>()?.parse()?,
+                false => input.parse()?
+            };
+            Ok(MacroOption::Name(key, name))
+        } else {
+            Err(Error::new(key.span(), format!("unknown option '{}', the supported options are {}", key, OPTION_NAMES)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::parse_quote;
+    use crate::options::Options;
+
+    #[test]
+    fn options_are_parsed() {
+        let options: Options = parse_quote! { module = imp, vis = pub(super), name = "derive_answer", catch_panics };
+
+        assert_eq!(options.module.unwrap(), "imp");
+        assert!(matches!(options.vis.unwrap(), syn::Visibility::Restricted(_)));
+        assert_eq!(options.name.unwrap(), "derive_answer");
+        assert!(options.catch_panics);
+    }
+
+    #[test]
+    fn misspelled_options_are_rejected() {
+        let error = syn::parse2::<Options>(quote::quote! { modul = imp }).err().expect("the option should be rejected");
+
+        assert_eq!(error.to_string(), "unknown option 'modul', the supported options are 'module', 'vis', 'name' and 'catch_panics'")
+    }
+}
@@ -0,0 +1,204 @@
+use std::ops::Deref;
+use quote::ToTokens;
+use syn::{Error, FnArg, GenericArgument, PathArguments, ReturnType, Signature, Type};
+
+/// The macros of this crate together with the function signatures they accept. Used to create
+/// error messages for functions with unexpected signatures.
+pub struct MacroKind {
+    pub name: &'static str,
+    pub parameter_count: usize,
+    pub accepted_signatures: &'static str,
+}
+
+pub const ATTRIBUTE: MacroKind = MacroKind {
+    name: "proc_macro_attribute2",
+    parameter_count: 2,
+    accepted_signatures: "(A, I) -> TokenStream2 or (A, I) -> syn::Result<TokenStream2>, where A is AttributeArgs or implements syn::parse::Parse and I is TokenStream2 or implements syn::parse::Parse",
+};
+
+pub const DERIVE: MacroKind = MacroKind {
+    name: "proc_macro_derive2",
+    parameter_count: 1,
+    accepted_signatures: "(DeriveInput) -> TokenStream2 or (DeriveInput) -> syn::Result<TokenStream2>",
+};
+
+pub const FUNCTION: MacroKind = MacroKind {
+    name: "proc_macro_fn2",
+    parameter_count: 1,
+    accepted_signatures: "(T) -> TokenStream2 or (T) -> syn::Result<TokenStream2>, where T is TokenStream2 or implements syn::parse::Parse",
+};
+
+/// The return types an annotated function may have.
+#[derive(Clone, Copy)]
+pub enum OutputKind {
+    /// TokenStream2
+    TokenStream,
+    /// syn::Result<TokenStream2>
+    Result,
+}
+
+/// Returns the attribute args type, the item type and the output kind of a 'proc_macro_attribute2' function.
+pub fn attribute_signature(sig: &Signature) -> syn::Result<(&Type, &Type, OutputKind)> {
+    let input_types = input_types(sig, &ATTRIBUTE)?;
+    let output_kind = output_kind(sig, &ATTRIBUTE)?;
+    Ok((input_types[0], input_types[1], output_kind))
+}
+
+/// Returns the output kind of a 'proc_macro_derive2' function.
+pub fn derive_signature(sig: &Signature) -> syn::Result<OutputKind> {
+    let input_types = input_types(sig, &DERIVE)?;
+
+    if !type_has_name(input_types[0], "DeriveInput") {
+        return Err(signature_error(input_types[0], sig, &DERIVE, "the parameter has to be DeriveInput"));
+    }
+
+    output_kind(sig, &DERIVE)
+}
+
+/// Returns the input type and the output kind of a 'proc_macro_fn2' function.
+pub fn fn_signature(sig: &Signature) -> syn::Result<(&Type, OutputKind)> {
+    let input_types = input_types(sig, &FUNCTION)?;
+    let output_kind = output_kind(sig, &FUNCTION)?;
+    Ok((input_types[0], output_kind))
+}
+
+pub fn type_has_name(ty: &Type, expected_type_name: &str) -> bool {
+    match ty {
+        Type::Path(p) => p.path.segments
+            .last()
+            .map(|seg| &seg.ident)
+            .map(|ident| ident == expected_type_name)
+            .unwrap_or(false),
+        _ => false
+    }
+}
+
+fn input_types<'a>(sig: &'a Signature, macro_kind: &MacroKind) -> syn::Result<Vec<&'a Type>> {
+    if sig.inputs.len() != macro_kind.parameter_count {
+        let problem = format!("expected {} parameter(s), found {}", macro_kind.parameter_count, sig.inputs.len());
+        return Err(match sig.inputs.is_empty() {
+            true => Error::new(sig.paren_token.span, signature_message(sig, macro_kind, &problem)),
+            false => signature_error(&sig.inputs, sig, macro_kind, &problem)
+        });
+    }
+
+    sig.inputs
+        .iter()
+        .map(|input| match input {
+            FnArg::Typed(typed) => Ok(typed.ty.deref()),
+            FnArg::Receiver(receiver) => Err(signature_error(receiver, sig, macro_kind, "'self' parameters are not supported")),
+        })
+        .collect()
+}
+
+fn output_kind(sig: &Signature, macro_kind: &MacroKind) -> syn::Result<OutputKind> {
+    let ty = match &sig.output {
+        ReturnType::Type(_, ty) => ty,
+        ReturnType::Default => return Err(Error::new(
+            sig.paren_token.span,
+            signature_message(sig, macro_kind, "the return type is missing"),
+        ))
+    };
+
+    if type_has_name(ty, "TokenStream2") {
+        return Ok(OutputKind::TokenStream);
+    }
+
+    match result_ok_type(ty) {
+        Some(ok_type) if type_has_name(ok_type, "TokenStream2") => Ok(OutputKind::Result),
+        _ => Err(signature_error(ty, sig, macro_kind, "the return type has to be TokenStream2 or syn::Result<TokenStream2>"))
+    }
+}
+
+/// Returns T if the given type is Result<T> or Result<T, E>.
+fn result_ok_type(ty: &Type) -> Option<&Type> {
+    let segment = match ty {
+        Type::Path(p) => p.path.segments.last()?,
+        _ => return None
+    };
+
+    if segment.ident != "Result" {
+        return None;
+    }
+
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) => match args.args.first() {
+            Some(GenericArgument::Type(ok_type)) => Some(ok_type),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+fn signature_error<T: ToTokens>(tokens: T, sig: &Signature, macro_kind: &MacroKind, problem: &str) -> Error {
+    Error::new_spanned(tokens, signature_message(sig, macro_kind, problem))
+}
+
+fn signature_message(sig: &Signature, macro_kind: &MacroKind, problem: &str) -> String {
+    format!(
+        "{}\n'{}' is only applicable on functions of type {}\nfound: {}",
+        problem,
+        macro_kind.name,
+        macro_kind.accepted_signatures,
+        signature_to_string(sig)
+    )
+}
+
+/// Renders the parameter and return types of a signature, e.g. '(AttributeArgs, String) -> TokenStream2'.
+fn signature_to_string(sig: &Signature) -> String {
+    let inputs = sig.inputs
+        .iter()
+        .map(|input| match input {
+            FnArg::Typed(typed) => tokens_to_string(&typed.ty),
+            FnArg::Receiver(receiver) => tokens_to_string(receiver),
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    match &sig.output {
+        ReturnType::Type(_, ty) => format!("({}) -> {}", inputs, tokens_to_string(ty)),
+        ReturnType::Default => format!("({})", inputs)
+    }
+}
+
+/// Turns tokens into a string without the spaces token streams put between every token.
+fn tokens_to_string<T: ToTokens>(tokens: T) -> String {
+    tokens.to_token_stream()
+        .to_string()
+        .replace(" :: ", "::")
+        .replace(":: ", "::")
+        .replace(" <", "<")
+        .replace("< ", "<")
+        .replace(" >", ">")
+        .replace(" ,", ",")
+        .replace("& ", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::{parse_quote, Signature};
+    use crate::signature::{attribute_signature, derive_signature};
+
+    #[test]
+    fn wrong_return_type_is_reported() {
+        let sig: Signature = parse_quote! { fn answer(args: AttributeArgs, item: TokenStream2) -> syn::Result<String> };
+        let error = attribute_signature(&sig).err().expect("the signature should be rejected");
+
+        assert_eq!(
+            error.to_string(),
+            "the return type has to be TokenStream2 or syn::Result<TokenStream2>\n\
+            'proc_macro_attribute2' is only applicable on functions of type (A, I) -> TokenStream2 or (A, I) -> syn::Result<TokenStream2>, \
+            where A is AttributeArgs or implements syn::parse::Parse and I is TokenStream2 or implements syn::parse::Parse\n\
+            found: (AttributeArgs, TokenStream2) -> syn::Result<String>"
+        )
+    }
+
+    #[test]
+    fn wrong_derive_parameter_is_reported() {
+        let sig: Signature = parse_quote! { fn derive_answer(item: ItemStruct) -> TokenStream2 };
+        let error = derive_signature(&sig).err().expect("the signature should be rejected");
+
+        assert!(error.to_string().starts_with("the parameter has to be DeriveInput\n"));
+        assert!(error.to_string().ends_with("found: (ItemStruct) -> TokenStream2"));
+    }
+}
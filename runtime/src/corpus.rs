@@ -0,0 +1,85 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use proc_macro2::TokenStream;
+
+/// If this environment variable is set to a directory, the attribute macros generated by
+/// 'proc_macro_attribute2' record every invocation into '<dir>/<macro name>/<case>'.
+pub const RECORD_VARIABLE: &str = "MACRO_TEST_RECORD";
+
+const ARGS_FILE: &str = "args.tokens";
+const ITEM_FILE: &str = "item.tokens";
+const OUTPUT_FILE: &str = "output.tokens";
+
+/// Tells if invocations are recorded, so the generated code only copies its input if needed.
+/// Used by the code generated by 'proc_macro_attribute2'.
+pub fn recording_enabled() -> bool {
+    std::env::var_os(RECORD_VARIABLE).is_some()
+}
+
+/// Writes the attribute arguments, the item and the output of an invocation into the corpus directory
+/// given by MACRO_TEST_RECORD. Used by the code generated by 'proc_macro_attribute2'.
+pub fn record_invocation(macro_name: &str, attribute_args: TokenStream, item: TokenStream, output: TokenStream) {
+    if let Some(corpus_dir) = std::env::var_os(RECORD_VARIABLE) {
+        if let Err(e) = write_case(&PathBuf::from(corpus_dir).join(macro_name), &attribute_args, &item, &output) {
+            panic!("Could not record the invocation of '{}': {}", macro_name, e)
+        }
+    }
+}
+
+/// An invocation recorded by 'record_invocation'. Used by macro_test to replay the corpus.
+pub struct RecordedCase {
+    pub dir: PathBuf,
+    pub attribute_args: TokenStream,
+    pub item: TokenStream,
+    pub output: TokenStream,
+}
+
+/// Reads all cases of a macro, i.e. the subdirectories of '<corpus dir>/<macro name>', sorted by name.
+pub fn read_cases(macro_dir: &Path) -> Vec<RecordedCase> {
+    let entries = fs::read_dir(macro_dir)
+        .unwrap_or_else(|e| panic!("Could not read the corpus {}: {}", macro_dir.display(), e));
+    let mut case_dirs = entries
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()
+        .unwrap_or_else(|e| panic!("Could not read the corpus {}: {}", macro_dir.display(), e));
+    case_dirs.retain(|dir| dir.is_dir());
+    case_dirs.sort();
+
+    case_dirs
+        .into_iter()
+        .map(|dir| RecordedCase {
+            attribute_args: read_tokens(&dir.join(ARGS_FILE)),
+            item: read_tokens(&dir.join(ITEM_FILE)),
+            output: read_tokens(&dir.join(OUTPUT_FILE)),
+            dir,
+        })
+        .collect()
+}
+
+/// Every case is stored in a directory named after the hash of its input, so recording the same invocation
+/// again (e.g. in another build) overwrites the case instead of adding a new one.
+pub fn write_case(macro_dir: &Path, attribute_args: &TokenStream, item: &TokenStream, output: &TokenStream) -> std::io::Result<PathBuf> {
+    let attribute_args = attribute_args.to_string();
+    let item = item.to_string();
+    let case_dir = macro_dir.join(format!("{:016x}", fnv1a(&[&attribute_args, &item])));
+
+    fs::create_dir_all(&case_dir)?;
+    fs::write(case_dir.join(ARGS_FILE), attribute_args)?;
+    fs::write(case_dir.join(ITEM_FILE), item)?;
+    fs::write(case_dir.join(OUTPUT_FILE), output.to_string())?;
+    Ok(case_dir)
+}
+
+fn read_tokens(path: &Path) -> TokenStream {
+    fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Could not read {}: {}", path.display(), e))
+        .parse()
+        .unwrap_or_else(|e| panic!("{} does not contain valid tokens: {}", path.display(), e))
+}
+
+/// FNV-1a, as the hash has to be the same for every build and Rust version.
+fn fnv1a(parts: &[&str]) -> u64 {
+    parts.iter()
+        .flat_map(|part| part.bytes().chain(Some(0)))
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
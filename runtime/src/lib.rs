@@ -0,0 +1,81 @@
+use std::any::TypeId;
+use proc_macro2::TokenStream;
+use syn::parse::Parse;
+pub use crate::corpus::{record_invocation, recording_enabled, RECORD_VARIABLE};
+pub use crate::panic::panic_to_compile_error;
+pub use attributes::{proc_macro_attribute2, proc_macro_derive2, proc_macro_fn2};
+// used by macro_test, which records and replays the corpus and reports panics like the generated code
+#[doc(hidden)]
+pub use crate::corpus::{read_cases, write_case, RecordedCase};
+#[doc(hidden)]
+pub use crate::panic::panic_message;
+
+mod corpus;
+mod panic;
+
+// the generated code uses these crates through macro_test_runtime, so proc macro crates don't need to
+// depend on (or import) them
+pub use ::proc_macro2;
+pub use ::quote;
+pub use ::syn;
+
+/// Parses the item an attribute was applied to into the item parameter type of the implementation.
+/// If the item has the wrong kind, the error spans the whole item and tells which kind of items the
+/// attribute can be applied to, e.g. 'this attribute can only be applied to structs' for syn::ItemStruct.
+/// Used by the code generated by 'proc_macro_attribute2' and by the test harness.
+pub fn parse_item<I: Parse + 'static>(item: TokenStream) -> syn::Result<I> {
+    syn::parse2::<I>(item.clone()).map_err(|error| match item_kind_description::<I>() {
+        Some(description) => syn::Error::new_spanned(
+            item,
+            format!("this attribute can only be applied to {}", description),
+        ),
+        None => error
+    })
+}
+
+fn item_kind_description<I: 'static>() -> Option<&'static str> {
+    let descriptions = [
+        (TypeId::of::<syn::ItemConst>(), "constants"),
+        (TypeId::of::<syn::ItemEnum>(), "enums"),
+        (TypeId::of::<syn::ItemExternCrate>(), "extern crate declarations"),
+        (TypeId::of::<syn::ItemFn>(), "functions"),
+        (TypeId::of::<syn::ItemForeignMod>(), "extern blocks"),
+        (TypeId::of::<syn::ItemImpl>(), "impl blocks"),
+        (TypeId::of::<syn::ItemMacro>(), "macro definitions"),
+        (TypeId::of::<syn::ItemMacro2>(), "macro definitions"),
+        (TypeId::of::<syn::ItemMod>(), "modules"),
+        (TypeId::of::<syn::ItemStatic>(), "statics"),
+        (TypeId::of::<syn::ItemStruct>(), "structs"),
+        (TypeId::of::<syn::ItemTrait>(), "traits"),
+        (TypeId::of::<syn::ItemTraitAlias>(), "trait aliases"),
+        (TypeId::of::<syn::ItemType>(), "type aliases"),
+        (TypeId::of::<syn::ItemUnion>(), "unions"),
+        (TypeId::of::<syn::ItemUse>(), "use declarations"),
+        (TypeId::of::<syn::DeriveInput>(), "structs, enums and unions"),
+        (TypeId::of::<syn::ImplItemMethod>(), "methods"),
+        (TypeId::of::<syn::ImplItemConst>(), "associated constants"),
+        (TypeId::of::<syn::ImplItemType>(), "associated types"),
+        (TypeId::of::<syn::TraitItemMethod>(), "trait methods"),
+        (TypeId::of::<syn::Variant>(), "enum variants"),
+        (TypeId::of::<syn::Stmt>(), "statements"),
+    ];
+
+    descriptions.iter()
+        .find(|(type_id, _)| *type_id == TypeId::of::<I>())
+        .map(|(_, description)| *description)
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::ItemStruct;
+
+    #[test]
+    fn item_kind_is_described_by_type() {
+        mod other {
+            pub struct ItemStruct;
+        }
+
+        assert_eq!(crate::item_kind_description::<ItemStruct>(), Some("structs"));
+        assert_eq!(crate::item_kind_description::<other::ItemStruct>(), None);
+    }
+}
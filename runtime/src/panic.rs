@@ -0,0 +1,33 @@
+use std::any::Any;
+use proc_macro2::TokenStream;
+
+/// Turns the payload of a panic into a compile error spanning the macro input, which names the macro and
+/// contains the panic message. Used by the code generated with the option 'catch_panics'.
+pub fn panic_to_compile_error(macro_name: &str, payload: Box<dyn Any + Send>, input: TokenStream) -> TokenStream {
+    let message = format!("the macro '{}' panicked: {}", macro_name, panic_message(payload.as_ref()));
+    syn::Error::new_spanned(input, message).to_compile_error()
+}
+
+/// The message of a panic, like the default panic hook shows it.
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload.downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "Box<dyn Any>".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::quote;
+    use crate::panic::panic_to_compile_error;
+
+    #[test]
+    fn panic_becomes_compile_error() {
+        let payload = std::panic::catch_unwind(|| panic!("no answer for {}", "S")).unwrap_err();
+
+        assert_eq!(
+            panic_to_compile_error("answer", payload, quote! { struct S; }).to_string(),
+            quote! { compile_error! { "the macro 'answer' panicked: no answer for S" } }.to_string()
+        )
+    }
+}
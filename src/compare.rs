@@ -0,0 +1,229 @@
+use std::fmt::{Debug, Display, Formatter};
+use proc_macro2::{Delimiter, Spacing, TokenStream, TokenTree};
+use quote::ToTokens;
+use syn::Item;
+use crate::diff::mismatch_report;
+use crate::panic::ImplementationPanic;
+use crate::pattern::printable_expectation;
+
+/// The first difference between two token streams.
+#[derive(Clone, Debug)]
+pub struct TokenMismatch {
+    /// The position of the differing token. Every entry is the index of a token tree in its
+    /// surrounding stream (after removing None-delimited groups), starting at the outermost stream.
+    pub path: Vec<usize>,
+    /// The differing token of the actual stream, None if the stream ended early.
+    pub actual: Option<TokenTree>,
+    /// The differing token of the expected stream, None if the stream ended early.
+    pub expected: Option<TokenTree>,
+}
+
+impl Display for TokenMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let describe = |tree: &Option<TokenTree>| match tree {
+            Some(tree) => format!("'{}'", describe_token(tree)),
+            None => "the end of the stream".to_string()
+        };
+
+        write!(
+            f,
+            "first difference at token {}: found {}, expected {}",
+            self.path.iter().map(|i| i.to_string()).collect::<Vec<_>>().join("."),
+            describe(&self.actual),
+            describe(&self.expected)
+        )
+    }
+}
+
+/// An attribute implementation whose expansion doesn't match the expectation, returned by
+/// 'try_compare_implementations'. Its Display implementation is the report 'compare_implementations' panics with.
+#[derive(Clone)]
+pub struct ExpansionMismatch {
+    /// The expansion of the implementation.
+    pub actual: TokenStream,
+    /// The expected expansion.
+    pub expected: TokenStream,
+    /// The first difference, including the path to the differing token.
+    pub mismatch: TokenMismatch,
+    /// The arguments of the attribute, i.e. the tokens between its delimiters. Empty for name-value attributes.
+    pub attribute_args: TokenStream,
+    /// The item given to the test without the attribute. If the attribute was on a nested node, this is the
+    /// item containing it.
+    pub item: Item,
+}
+
+impl Display for ExpansionMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&mismatch_report(&self.actual, &printable_expectation(&self.expected), &self.mismatch))
+    }
+}
+
+impl Debug for ExpansionMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExpansionMismatch")
+            .field("actual", &self.actual)
+            .field("expected", &self.expected)
+            .field("mismatch", &self.mismatch)
+            .field("attribute_args", &self.attribute_args)
+            .field("item", &DebugItem(&self.item))
+            .finish()
+    }
+}
+
+impl std::error::Error for ExpansionMismatch {}
+
+/// Shows an item as its tokens in Debug output, as syn only implements Debug with its 'extra-traits' feature.
+pub(crate) struct DebugItem<'a>(pub &'a Item);
+
+impl Debug for DebugItem<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&self.0.to_token_stream(), f)
+    }
+}
+
+/// Why 'try_compare_implementations' failed.
+#[derive(Debug)]
+pub enum ExpansionFailure {
+    /// The implementation returned an expansion which doesn't match the expectation.
+    Mismatch(Box<ExpansionMismatch>),
+    /// The implementation panicked.
+    Panic(Box<ImplementationPanic>),
+    /// The item doesn't carry the tested attribute. Contains the message telling where it was searched.
+    AttributeNotFound(String),
+    /// The item can't be prepared for the expansion, e.g. because a 'cfg_attr' is malformed.
+    InvalidItem(String),
+}
+
+impl Display for ExpansionFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExpansionFailure::Mismatch(mismatch) => Display::fmt(mismatch, f),
+            ExpansionFailure::Panic(panic) => Display::fmt(panic, f),
+            ExpansionFailure::AttributeNotFound(message) | ExpansionFailure::InvalidItem(message) => f.write_str(message)
+        }
+    }
+}
+
+impl std::error::Error for ExpansionFailure {}
+
+/// Compares two token streams structurally, tree by tree. Idents, literals, puncts and group delimiters
+/// have to be equal, while spans and whitespace are ignored. The spacing of a punct is only relevant if
+/// it is followed by another punct, as it only tells whether both are joined (like '--' vs. '- -').
+/// The quote of a lifetime is an exception, see 'effective_spacing'.
+/// None-delimited groups are transparent, their content is compared as if it was not grouped.
+pub fn compare_token_streams(actual: &TokenStream, expected: &TokenStream) -> Result<(), TokenMismatch> {
+    let mut path = vec![];
+    compare_streams(&flatten(actual), &flatten(expected), &mut path)
+}
+
+fn compare_streams(actual: &[TokenTree], expected: &[TokenTree], path: &mut Vec<usize>) -> Result<(), TokenMismatch> {
+    for index in 0..actual.len().max(expected.len()) {
+        path.push(index);
+
+        match (actual.get(index), expected.get(index)) {
+            (Some(TokenTree::Group(a)), Some(TokenTree::Group(e))) if a.delimiter() == e.delimiter() => {
+                compare_streams(&flatten(&a.stream()), &flatten(&e.stream()), path)?
+            }
+            (Some(a), Some(e)) if trees_equal(a, actual.get(index + 1), e, expected.get(index + 1)) => {}
+            (a, e) => return Err(TokenMismatch {
+                path: path.clone(),
+                actual: a.cloned(),
+                expected: e.cloned(),
+            })
+        }
+
+        path.pop();
+    }
+
+    Ok(())
+}
+
+/// Compares two non-group trees. The following trees are needed to decide if the spacing of puncts matters.
+pub(crate) fn trees_equal(actual: &TokenTree, actual_next: Option<&TokenTree>, expected: &TokenTree, expected_next: Option<&TokenTree>) -> bool {
+    match (actual, expected) {
+        (TokenTree::Ident(a), TokenTree::Ident(e)) => a == e,
+        (TokenTree::Literal(a), TokenTree::Literal(e)) => a.to_string() == e.to_string(),
+        (TokenTree::Punct(a), TokenTree::Punct(e)) => a.as_char() == e.as_char()
+            && effective_spacing(a.spacing(), actual_next) == effective_spacing(e.spacing(), expected_next),
+        _ => false
+    }
+}
+
+/// The quote of a lifetime never joins with the punct before it, so rustc's Joint in '<'a>' and the Alone
+/// printed by syn are the same.
+fn effective_spacing(spacing: Spacing, next: Option<&TokenTree>) -> Spacing {
+    match next {
+        Some(TokenTree::Punct(next)) if next.as_char() != '\'' => spacing,
+        _ => Spacing::Alone
+    }
+}
+
+/// Returns the trees of the stream, with the content of None-delimited groups inlined.
+pub(crate) fn flatten(stream: &TokenStream) -> Vec<TokenTree> {
+    stream.clone()
+        .into_iter()
+        .flat_map(|tree| match tree {
+            TokenTree::Group(group) if group.delimiter() == Delimiter::None => flatten(&group.stream()),
+            tree => vec![tree]
+        })
+        .collect()
+}
+
+fn describe_token(tree: &TokenTree) -> String {
+    match tree {
+        TokenTree::Group(group) => match group.delimiter() {
+            Delimiter::Parenthesis => "( ... )".to_string(),
+            Delimiter::Brace => "{ ... }".to_string(),
+            Delimiter::Bracket => "[ ... ]".to_string(),
+            Delimiter::None => group.stream().to_string(),
+        },
+        TokenTree::Punct(punct) if punct.spacing() == Spacing::Joint => format!("{} (joint)", punct.as_char()),
+        tree => tree.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use proc_macro2::{Delimiter, Group, TokenStream, TokenTree};
+    use crate::compare::compare_token_streams;
+
+    fn stream(s: &str) -> TokenStream {
+        TokenStream::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn whitespace_is_ignored() {
+        let result = compare_token_streams(&stream("pub struct Foo<'a> { a: &'a str }"), &stream("pub   struct\nFoo < 'a >{a : &'a str}"));
+        assert!(result.is_ok(), "{}", result.unwrap_err())
+    }
+
+    #[test]
+    fn joined_idents_differ() {
+        let mismatch = compare_token_streams(&stream("pubstruct Foo;"), &stream("pub struct Foo;")).unwrap_err();
+
+        assert_eq!(mismatch.path, vec![0]);
+        assert_eq!(mismatch.to_string(), "first difference at token 0: found 'pubstruct', expected 'pub'")
+    }
+
+    #[test]
+    fn punct_spacing_matters() {
+        let mismatch = compare_token_streams(&stream("{ a - -b }"), &stream("{ a --b }")).unwrap_err();
+
+        assert_eq!(mismatch.path, vec![0, 1]);
+        assert_eq!(mismatch.to_string(), "first difference at token 0.1: found '-', expected '- (joint)'")
+    }
+
+    #[test]
+    fn delimiters_matter() {
+        assert!(compare_token_streams(&stream("foo(a)"), &stream("foo[a]")).is_err())
+    }
+
+    #[test]
+    fn none_delimited_groups_are_transparent() {
+        let mut grouped = TokenStream::from(TokenTree::Group(Group::new(Delimiter::None, stream("1 + 2"))));
+        grouped.extend(stream("* 3"));
+
+        assert!(compare_token_streams(&grouped, &stream("1 + 2 * 3")).is_ok())
+    }
+}
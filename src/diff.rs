@@ -0,0 +1,248 @@
+use std::io::IsTerminal;
+use std::path::Path;
+use proc_macro2::TokenStream;
+use similar::{ChangeTag, TextDiff};
+use crate::compare::TokenMismatch;
+use crate::snapshot::BLESS_VARIABLE;
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const CYAN: &str = "\x1b[36m";
+const HIGHLIGHT: &str = "\x1b[1;4m";
+const RESET: &str = "\x1b[0m";
+
+/// Creates the report for an implementation that doesn't match the expectation: the first differing
+/// token and a unified diff of both token streams, pretty-printed as Rust code if possible.
+/// The report is colored if stderr is a terminal, unless NO_COLOR is set.
+pub fn mismatch_report(actual: &TokenStream, expected: &TokenStream, mismatch: &TokenMismatch) -> String {
+    create_report(actual, expected, mismatch, colored())
+}
+
+/// Creates the report for two implementations whose expansions of the same input differ, like
+/// 'mismatch_report' with the old expansion as expectation.
+pub fn equivalence_report(old: &TokenStream, new: &TokenStream, mismatch: &TokenMismatch) -> String {
+    let (new, old) = printed_texts(new, old);
+    let mut report = format!("The new implementation differs from the old one, {}\n", mismatch);
+    report.push_str(&unified_diff(&new, &old, "old", "new", colored()));
+    report
+}
+
+fn colored() -> bool {
+    std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+}
+
+/// Formats a token stream as Rust code. Token streams which are not a valid Rust file (like the
+/// expression a function-like macro may expand to) are printed as they are.
+pub fn pretty_print(tokens: &TokenStream) -> String {
+    match syn::parse2::<syn::File>(tokens.clone()) {
+        Ok(file) => prettyplease::unparse(&file),
+        Err(_) => tokens.to_string()
+    }
+}
+
+/// Creates the report for an expansion that doesn't match the stored snapshot, both given as pretty-printed code.
+pub fn snapshot_report(actual: &str, stored: &str, snapshot: &Path, pending: &Path) -> String {
+    let mut report = format!(
+        "The implementation does not match the snapshot {}\nThe new snapshot was written to {}, rename it or rerun with {}=1 to accept it\n",
+        snapshot.display(),
+        pending.display(),
+        BLESS_VARIABLE
+    );
+    report.push_str(&unified_diff(actual, stored, "snapshot", "actual", colored()));
+    report
+}
+
+fn create_report(actual: &TokenStream, expected: &TokenStream, mismatch: &TokenMismatch, colored: bool) -> String {
+    let (actual, expected) = printed_texts(actual, expected);
+    let mut report = format!("The implementation does not match the expectation, {}\n", mismatch);
+    report.push_str(&unified_diff(&actual, &expected, "expected", "actual", colored));
+    report
+}
+
+/// Differences like the spacing of puncts may disappear when pretty-printing, the raw tokens show them.
+fn printed_texts(actual: &TokenStream, expected: &TokenStream) -> (String, String) {
+    match (pretty_print(actual), pretty_print(expected)) {
+        (pretty_actual, pretty_expected) if pretty_actual == pretty_expected => (actual.to_string() + "\n", expected.to_string() + "\n"),
+        pretty => pretty
+    }
+}
+
+fn unified_diff(actual: &str, expected: &str, expected_label: &str, actual_label: &str, colored: bool) -> String {
+    let mut report = paint(&format!("--- {}", expected_label), RED, colored);
+    report.push_str(&paint(&format!("+++ {}", actual_label), GREEN, colored));
+
+    let diff = TextDiff::from_lines(expected, actual);
+    let mut first_change = FirstChange::default();
+
+    for group in diff.grouped_ops(3) {
+        let first_op = group.first().expect("groups are never empty");
+        let last_op = group.last().expect("groups are never empty");
+        report.push_str(&paint(&format!(
+            "@@ -{},{} +{},{} @@",
+            first_op.old_range().start + 1,
+            last_op.old_range().end - first_op.old_range().start,
+            first_op.new_range().start + 1,
+            last_op.new_range().end - first_op.new_range().start,
+        ), CYAN, colored));
+
+        for change in group.iter().flat_map(|op| diff.iter_changes(op)) {
+            let line = change.value().trim_end_matches('\n');
+            let (sign, color) = match change.tag() {
+                ChangeTag::Equal => (' ', None),
+                ChangeTag::Delete => ('-', Some(RED)),
+                ChangeTag::Insert => ('+', Some(GREEN)),
+            };
+
+            match color {
+                Some(color) => {
+                    let line = match colored {
+                        true => first_change.highlight(change.tag(), line, color, expected, actual),
+                        false => line.to_string()
+                    };
+                    report.push_str(&paint(&format!("{}{}", sign, line), color, colored))
+                }
+                None => report.push_str(&format!("{}{}\n", sign, line))
+            }
+        }
+    }
+
+    report
+}
+
+/// Highlights the first diverging token in the first deleted and the first inserted line.
+#[derive(Default)]
+struct FirstChange {
+    delete_done: bool,
+    insert_done: bool,
+}
+
+impl FirstChange {
+    fn highlight(&mut self, tag: ChangeTag, line: &str, line_color: &str, expected: &str, actual: &str) -> String {
+        let done = match tag {
+            ChangeTag::Equal => return line.to_string(),
+            ChangeTag::Delete => &mut self.delete_done,
+            ChangeTag::Insert => &mut self.insert_done,
+        };
+
+        if *done {
+            return line.to_string();
+        }
+        *done = true;
+
+        // the first differing char of both texts lies in the first changed line
+        let divergence = first_divergence(expected, actual);
+        let line_start = match tag {
+            ChangeTag::Delete => line_start_of(expected, divergence),
+            _ => line_start_of(actual, divergence),
+        };
+        let column = token_start(line, divergence - line_start);
+
+        match line.get(column..) {
+            Some(rest) if !rest.is_empty() => {
+                let token_len = token_length(rest);
+                format!("{}{}{}{}{}{}", &line[..column], HIGHLIGHT, &rest[..token_len], RESET, line_color, &rest[token_len..])
+            }
+            _ => line.to_string()
+        }
+    }
+}
+
+fn first_divergence(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .find(|((_, ca), cb)| ca != cb)
+        .map(|((i, _), _)| i)
+        .unwrap_or_else(|| a.len().min(b.len()))
+}
+
+fn line_start_of(text: &str, position: usize) -> usize {
+    text[..position.min(text.len())].rfind('\n').map(|i| i + 1).unwrap_or(0)
+}
+
+/// Moves the column back to the start of the word it lies in.
+fn token_start(line: &str, column: usize) -> usize {
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+
+    match line.get(column..).and_then(|rest| rest.chars().next()) {
+        Some(c) if is_word_char(c) => line[..column]
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| is_word_char(*c))
+            .last()
+            .map(|(i, _)| i)
+            .unwrap_or(column),
+        _ => column
+    }
+}
+
+/// The length of the token at the start of the text: a whole word or a single other char.
+fn token_length(text: &str) -> usize {
+    let word_length = text
+        .char_indices()
+        .find(|(_, c)| !(c.is_alphanumeric() || *c == '_'))
+        .map(|(i, _)| i)
+        .unwrap_or(text.len());
+
+    match word_length {
+        0 => text.chars().next().map(char::len_utf8).unwrap_or(0),
+        length => length
+    }
+}
+
+/// Returns the text as line, colored if requested.
+fn paint(text: &str, color: &str, colored: bool) -> String {
+    match colored {
+        true => format!("{}{}{}\n", color, text, RESET),
+        false => format!("{}\n", text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::quote;
+    use crate::compare::compare_token_streams;
+    use crate::diff::create_report;
+
+    #[test]
+    fn report_contains_unified_diff() {
+        let actual = quote! { struct S; impl S { fn get_answer() -> usize { 41 } } };
+        let expected = quote! { struct S; impl S { fn get_answer() -> usize { 42 } } };
+        let mismatch = compare_token_streams(&actual, &expected).unwrap_err();
+
+        assert_eq!(
+            create_report(&actual, &expected, &mismatch, false),
+            "The implementation does not match the expectation, first difference at token 5.6.0: found '41', expected '42'\n\
+            --- expected\n\
+            +++ actual\n\
+            @@ -1,6 +1,6 @@\n \
+            struct S;\n \
+            impl S {\n     \
+            fn get_answer() -> usize {\n\
+            -        42\n\
+            +        41\n     \
+            }\n \
+            }\n"
+        )
+    }
+
+    #[test]
+    fn first_diverging_token_is_highlighted() {
+        let actual = quote! { fn get_answer() -> usize { 41 } };
+        let expected = quote! { fn get_answer() -> usize { 42 } };
+        let mismatch = compare_token_streams(&actual, &expected).unwrap_err();
+        let report = create_report(&actual, &expected, &mismatch, true);
+
+        assert!(report.contains("\x1b[31m-    \x1b[1;4m42\x1b[0m\x1b[31m\x1b[0m\n"));
+        assert!(report.contains("\x1b[32m+    \x1b[1;4m41\x1b[0m\x1b[32m\x1b[0m\n"));
+    }
+
+    #[test]
+    fn raw_tokens_are_shown_if_pretty_printing_hides_the_difference() {
+        let actual = quote! { fn f() { a - -b } };
+        let expected: proc_macro2::TokenStream = "fn f() { a --b }".parse().unwrap();
+        let mismatch = compare_token_streams(&actual, &expected).unwrap_err();
+        let report = create_report(&actual, &expected, &mismatch, false);
+
+        assert!(report.ends_with("-fn f () { a -- b }\n+fn f () { a - - b }\n"));
+    }
+}
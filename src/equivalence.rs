@@ -0,0 +1,85 @@
+use std::path::Path;
+use proc_macro2::TokenStream;
+use syn::Item;
+use macro_test_runtime::read_cases;
+use crate::diff::pretty_print;
+use crate::extract::{attribute_args_tokens, extract_attribute_from_item};
+use crate::matcher::AttributeMatcher;
+use crate::property::PropertyTest;
+
+/// The inputs 'assert_implementations_equivalent' and 'record_corpus' expand: hand-written items, generated
+/// items or the cases of a recorded corpus. Inputs of different sources can be combined with 'and'.
+#[derive(Default)]
+pub struct Inputs {
+    pub(crate) inputs: Vec<Input>,
+}
+
+/// The arguments and the annotated node of an invocation, like the generated attribute gets them.
+pub(crate) struct Input {
+    pub description: String,
+    pub attribute_args: syn::Result<TokenStream>,
+    pub target: TokenStream,
+}
+
+impl Input {
+    /// Shows the input in reports.
+    pub fn describe(&self) -> String {
+        let attribute_args = self.attribute_args.as_ref().map(|args| args.to_string()).unwrap_or_else(|e| e.to_string());
+        format!("{}\nAttribute arguments: {}\nItem:\n{}", self.description, attribute_args, pretty_print(&self.target))
+    }
+}
+
+impl Inputs {
+    /// Items carrying the attribute, which is found like in 'compare_implementations'.
+    pub fn items<T: IntoIterator<Item = Item>>(matcher: &AttributeMatcher, items: T) -> Self {
+        let inputs = items.into_iter()
+            .enumerate()
+            .map(|(index, mut item)| {
+                let (attribute, target) = extract_attribute_from_item(matcher, &mut item);
+                Input {
+                    description: format!("Item {}", index),
+                    attribute_args: attribute_args_tokens(&attribute),
+                    target,
+                }
+            })
+            .collect();
+
+        Inputs { inputs }
+    }
+
+    /// The items the property test generates, see 'PropertyTest'.
+    pub fn generated(test: &PropertyTest) -> Self {
+        let mut inputs = Inputs::items(&test.matcher(), test.items());
+        for (index, input) in inputs.inputs.iter_mut().enumerate() {
+            input.description = format!("Generated item {}", index);
+        }
+        inputs
+    }
+
+    /// The recorded invocations of a macro, see 'replay_corpus'. Their recorded outputs are ignored.
+    pub fn corpus(macro_dir: &Path) -> Self {
+        let inputs = read_cases(macro_dir)
+            .into_iter()
+            .map(|case| Input {
+                description: format!("Recorded case {}", case.dir.display()),
+                attribute_args: Ok(case.attribute_args),
+                target: case.item,
+            })
+            .collect();
+
+        Inputs { inputs }
+    }
+
+    pub fn and(mut self, other: Inputs) -> Self {
+        self.inputs.extend(other.inputs);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+}
@@ -0,0 +1,175 @@
+use std::fmt::{Display, Formatter};
+use proc_macro2::{Span, TokenStream, TokenTree};
+use regex::Regex;
+use syn::LitStr;
+use crate::diff::pretty_print;
+
+/// How the message of an expected error is matched.
+#[derive(Clone, Copy, Debug)]
+pub enum ErrorMessage<'a> {
+    /// The message contains the text.
+    Substring(&'a str),
+    /// The message matches the regular expression.
+    Regex(&'a str),
+}
+
+impl ErrorMessage<'_> {
+    fn matches(&self, message: &str) -> bool {
+        match self {
+            ErrorMessage::Substring(text) => message.contains(text),
+            ErrorMessage::Regex(pattern) => Regex::new(pattern)
+                .unwrap_or_else(|e| panic!("Invalid regex for the expected error: {}", e))
+                .is_match(message)
+        }
+    }
+}
+
+impl Display for ErrorMessage<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorMessage::Substring(text) => write!(f, "containing '{}'", text),
+            ErrorMessage::Regex(pattern) => write!(f, "matching the regex '{}'", pattern)
+        }
+    }
+}
+
+/// A 'compile_error!' found in an expansion.
+struct EmittedError {
+    message: String,
+    span: Span,
+}
+
+impl Display for EmittedError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.span.source_text() {
+            Some(text) => write!(f, "'{}' at '{}'", self.message, text),
+            None => write!(f, "'{}' without location", self.message)
+        }
+    }
+}
+
+/// Checks that the expansion contains a 'compile_error!' with a matching message. If a span token is given,
+/// the span of the error has to cover a token of the item which is written like it, e.g. the ident 'bad'.
+/// Spans can only be compared if the item was parsed from a string, as the tokens created by quote all
+/// have the same span outside of proc macros.
+pub fn check_expected_error(expansion: &TokenStream, item: &TokenStream, message: ErrorMessage, span_token: Option<&str>) -> Result<(), String> {
+    let errors = emitted_errors(expansion);
+
+    if errors.is_empty() {
+        return Err(format!("The implementation did not emit an error, it expanded to:\n{}", pretty_print(expansion)));
+    }
+
+    let matching_errors = errors.iter().filter(|e| message.matches(&e.message)).collect::<Vec<_>>();
+
+    if matching_errors.is_empty() {
+        return Err(format!("The implementation did not emit an error {}, the emitted errors are:\n{}", message, list(&errors)));
+    }
+
+    let span_token = match span_token {
+        Some(span_token) => span_token,
+        None => return Ok(())
+    };
+
+    let token_spans = token_spans(item, span_token);
+
+    if token_spans.is_empty() {
+        panic!("The token '{}' does not occur in the item", span_token);
+    }
+
+    match matching_errors.iter().any(|e| token_spans.iter().any(|token| covers(e.span, *token))) {
+        true => Ok(()),
+        false => Err(format!(
+            "No error {} covers the token '{}', the matching errors are:\n{}",
+            message,
+            span_token,
+            list(&matching_errors)
+        ))
+    }
+}
+
+/// Returns the errors of all 'compile_error!' invocations in the stream, like the ones created by
+/// syn::Error::to_compile_error. The span of an error reaches from 'compile_error' to its arguments.
+fn emitted_errors(stream: &TokenStream) -> Vec<EmittedError> {
+    let trees = stream.clone().into_iter().collect::<Vec<_>>();
+    let mut errors = vec![];
+
+    for (index, tree) in trees.iter().enumerate() {
+        match (tree, trees.get(index + 1), trees.get(index + 2)) {
+            (TokenTree::Ident(ident), Some(TokenTree::Punct(bang)), Some(TokenTree::Group(args)))
+            if ident == "compile_error" && bang.as_char() == '!' => {
+                if let Ok(message) = syn::parse2::<LitStr>(args.stream()) {
+                    errors.push(EmittedError {
+                        message: message.value(),
+                        span: ident.span().join(args.span()).unwrap_or_else(|| ident.span()),
+                    })
+                }
+            }
+            (TokenTree::Group(group), _, _) => errors.extend(emitted_errors(&group.stream())),
+            _ => {}
+        }
+    }
+
+    errors
+}
+
+fn token_spans(stream: &TokenStream, token: &str) -> Vec<Span> {
+    stream.clone()
+        .into_iter()
+        .flat_map(|tree| match tree {
+            TokenTree::Group(group) => token_spans(&group.stream(), token),
+            tree if tree.to_string() == token => vec![tree.span()],
+            _ => vec![]
+        })
+        .collect()
+}
+
+/// Spans can only be joined if they belong to the same source, which makes the positions comparable.
+fn covers(outer: Span, inner: Span) -> bool {
+    outer.join(inner).is_some() && outer.start() <= inner.start() && inner.end() <= outer.end()
+}
+
+fn list<E: Display>(errors: &[E]) -> String {
+    errors.iter().map(|e| format!("- {}\n", e)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use proc_macro2::TokenStream;
+    use syn::ItemStruct;
+    use crate::expected_error::{check_expected_error, ErrorMessage};
+
+    fn item() -> TokenStream {
+        "struct S { good: u8, bad: u8 }".parse().unwrap()
+    }
+
+    fn error_at_field(item: &TokenStream, index: usize) -> TokenStream {
+        let item = syn::parse2::<ItemStruct>(item.clone()).unwrap();
+        let field = item.fields.iter().nth(index).unwrap();
+        syn::Error::new_spanned(field, "fields must not be named 'bad'").to_compile_error()
+    }
+
+    #[test]
+    fn error_covering_the_token_is_accepted() {
+        let item = item();
+        let result = check_expected_error(&error_at_field(&item, 1), &item, ErrorMessage::Regex("^fields .* 'bad'$"), Some("bad"));
+        assert!(result.is_ok(), "{}", result.unwrap_err())
+    }
+
+    #[test]
+    fn error_at_another_token_is_rejected() {
+        let item = item();
+        let report = check_expected_error(&error_at_field(&item, 0), &item, ErrorMessage::Substring("must not"), Some("bad")).unwrap_err();
+
+        assert_eq!(
+            report,
+            "No error containing 'must not' covers the token 'bad', the matching errors are:\n\
+            - 'fields must not be named 'bad'' at 'good: u8'\n"
+        )
+    }
+
+    #[test]
+    fn missing_error_is_reported() {
+        let report = check_expected_error(&item(), &item(), ErrorMessage::Substring("bad"), None).unwrap_err();
+        assert!(report.starts_with("The implementation did not emit an error, it expanded to:\n"))
+    }
+}
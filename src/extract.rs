@@ -0,0 +1,341 @@
+use proc_macro2::{Delimiter, TokenStream, TokenTree};
+use quote::{quote, ToTokens};
+use syn::{Attribute, Error, Expr, Field, ForeignItem, ImplItem, Item, Local, Stmt, TraitItem, Variant};
+use syn::parse::Parser;
+use syn::visit_mut::{self, VisitMut};
+use crate::matcher::AttributeMatcher;
+
+/// Removes the attribute from the item and returns it together with the tokens of the annotated node.
+/// The attribute is searched on the item itself and on the nodes nested in it, like the methods of an impl
+/// block, trait items, fields, enum variants and statements. The first annotated node wins, outer nodes
+/// before inner ones. The item keeps all other attributes and the rest of its content.
+/// If the matcher has cfgs, the 'cfg_attr' attributes of the item are expanded first.
+pub fn extract_attribute_from_item(matcher: &AttributeMatcher, item: &mut Item) -> (Attribute, TokenStream) {
+    try_extract_attribute_from_item(matcher, item)
+        .unwrap_or_else(|message| panic!("{}", message))
+        .unwrap_or_else(|| panic!("{}", missing_attribute_message(matcher, item)))
+}
+
+/// Tells where the missing attribute was searched.
+pub fn missing_attribute_message(matcher: &AttributeMatcher, item: &Item) -> String {
+    match item {
+        Item::Verbatim(_) => format!("Could not find the attribute {} on the item", matcher.describe()),
+        _ => format!(
+            "Could not find the attribute {} on the item or on a nested impl item, trait item, field, variant or statement",
+            matcher.describe()
+        )
+    }
+}
+
+/// Like 'extract_attribute_from_item', but returns None if the attribute is missing and an error if the
+/// 'cfg_attr' attributes of the item can't be expanded.
+pub fn try_extract_attribute_from_item(matcher: &AttributeMatcher, item: &mut Item) -> Result<Option<(Attribute, TokenStream)>, String> {
+    matcher.expand_cfg_attrs(item)?;
+
+    if let Item::Verbatim(tokens) = item {
+        return Ok(extract_attribute_from_tokens(matcher, tokens));
+    }
+
+    let mut extractor = AttributeExtractor { matcher, found: None };
+    extractor.visit_item_mut(item);
+    Ok(extractor.found)
+}
+
+/// Returns the tokens rustc passes to the attribute macro: nothing for '#[attr]' and the tokens between the
+/// delimiters for '#[attr(..)]', '#[attr[..]]' and '#[attr{..}]', whatever they are. Like rustc, name-value
+/// attributes like '#[attr = "x"]' are rejected with an error spanning the attribute.
+pub fn attribute_args_tokens(attribute: &Attribute) -> syn::Result<TokenStream> {
+    let mut tokens = attribute.tokens.clone().into_iter();
+
+    match (tokens.next(), tokens.next()) {
+        (None, _) => Ok(TokenStream::new()),
+        (Some(TokenTree::Group(group)), None) if group.delimiter() != Delimiter::None => Ok(group.stream()),
+        (Some(TokenTree::Punct(eq)), Some(_)) if eq.as_char() == '=' => Err(Error::new_spanned(
+            attribute,
+            "key-value macro attributes are not supported",
+        )),
+        _ => Err(Error::new_spanned(
+            &attribute.tokens,
+            "expected the arguments of the attribute in parentheses, brackets or braces",
+        ))
+    }
+}
+
+struct AttributeExtractor<'a> {
+    matcher: &'a AttributeMatcher,
+    found: Option<(Attribute, TokenStream)>,
+}
+
+impl AttributeExtractor<'_> {
+    /// Takes the attribute from the attributes of the node. Returns true if the search is over.
+    fn extract<T: ToTokens>(&mut self, node: &mut T, attributes: fn(&mut T) -> Option<&mut Vec<Attribute>>) -> bool {
+        if self.found.is_some() {
+            return true;
+        }
+
+        let attribute = attributes(node).and_then(|attributes| {
+            let index = attributes.iter().position(|a| self.matcher.matches(a))?;
+            Some(attributes.remove(index))
+        });
+
+        match attribute {
+            Some(attribute) => {
+                self.found = Some((attribute, node.to_token_stream()));
+                true
+            }
+            None => false
+        }
+    }
+}
+
+impl VisitMut for AttributeExtractor<'_> {
+    fn visit_item_mut(&mut self, node: &mut Item) {
+        if !self.extract(node, item_attributes) {
+            visit_mut::visit_item_mut(self, node)
+        }
+    }
+
+    fn visit_impl_item_mut(&mut self, node: &mut ImplItem) {
+        if !self.extract(node, impl_item_attributes) {
+            visit_mut::visit_impl_item_mut(self, node)
+        }
+    }
+
+    fn visit_trait_item_mut(&mut self, node: &mut TraitItem) {
+        if !self.extract(node, trait_item_attributes) {
+            visit_mut::visit_trait_item_mut(self, node)
+        }
+    }
+
+    fn visit_foreign_item_mut(&mut self, node: &mut ForeignItem) {
+        if !self.extract(node, foreign_item_attributes) {
+            visit_mut::visit_foreign_item_mut(self, node)
+        }
+    }
+
+    fn visit_field_mut(&mut self, node: &mut Field) {
+        if !self.extract(node, |field| Some(&mut field.attrs)) {
+            visit_mut::visit_field_mut(self, node)
+        }
+    }
+
+    fn visit_variant_mut(&mut self, node: &mut Variant) {
+        if !self.extract(node, |variant| Some(&mut variant.attrs)) {
+            visit_mut::visit_variant_mut(self, node)
+        }
+    }
+
+    fn visit_stmt_mut(&mut self, node: &mut Stmt) {
+        if !self.extract(node, stmt_attributes) {
+            visit_mut::visit_stmt_mut(self, node)
+        }
+    }
+}
+
+/// Searches the attribute in the tokens of an item syn couldn't parse. Only the attributes in front of the
+/// item are considered.
+fn extract_attribute_from_tokens(matcher: &AttributeMatcher, tokens: &mut TokenStream) -> Option<(Attribute, TokenStream)> {
+    let trees = tokens.clone().into_iter().collect::<Vec<_>>();
+    let mut index = 0;
+
+    while let (Some(TokenTree::Punct(pound)), Some(TokenTree::Group(group))) = (trees.get(index), trees.get(index + 1)) {
+        if pound.as_char() != '#' || group.delimiter() != Delimiter::Bracket {
+            break;
+        }
+
+        let attribute = Attribute::parse_outer.parse2(quote! {#pound #group}).ok()?.pop()?;
+
+        if matcher.matches(&attribute) {
+            *tokens = trees[..index].iter().chain(&trees[index + 2..]).cloned().collect();
+            return Some((attribute, tokens.clone()));
+        }
+
+        index += 2;
+    }
+
+    None
+}
+
+pub fn item_attributes(item: &mut Item) -> Option<&mut Vec<Attribute>> {
+    match item {
+        Item::Const(i) => Some(&mut i.attrs),
+        Item::Enum(i) => Some(&mut i.attrs),
+        Item::ExternCrate(i) => Some(&mut i.attrs),
+        Item::Fn(i) => Some(&mut i.attrs),
+        Item::ForeignMod(i) => Some(&mut i.attrs),
+        Item::Impl(i) => Some(&mut i.attrs),
+        Item::Macro(i) => Some(&mut i.attrs),
+        Item::Macro2(i) => Some(&mut i.attrs),
+        Item::Mod(i) => Some(&mut i.attrs),
+        Item::Static(i) => Some(&mut i.attrs),
+        Item::Struct(i) => Some(&mut i.attrs),
+        Item::Trait(i) => Some(&mut i.attrs),
+        Item::TraitAlias(i) => Some(&mut i.attrs),
+        Item::Type(i) => Some(&mut i.attrs),
+        Item::Union(i) => Some(&mut i.attrs),
+        Item::Use(i) => Some(&mut i.attrs),
+        _ => None
+    }
+}
+
+pub fn impl_item_attributes(item: &mut ImplItem) -> Option<&mut Vec<Attribute>> {
+    match item {
+        ImplItem::Const(i) => Some(&mut i.attrs),
+        ImplItem::Method(i) => Some(&mut i.attrs),
+        ImplItem::Type(i) => Some(&mut i.attrs),
+        ImplItem::Macro(i) => Some(&mut i.attrs),
+        _ => None
+    }
+}
+
+pub fn trait_item_attributes(item: &mut TraitItem) -> Option<&mut Vec<Attribute>> {
+    match item {
+        TraitItem::Const(i) => Some(&mut i.attrs),
+        TraitItem::Method(i) => Some(&mut i.attrs),
+        TraitItem::Type(i) => Some(&mut i.attrs),
+        TraitItem::Macro(i) => Some(&mut i.attrs),
+        _ => None
+    }
+}
+
+pub fn foreign_item_attributes(item: &mut ForeignItem) -> Option<&mut Vec<Attribute>> {
+    match item {
+        ForeignItem::Fn(i) => Some(&mut i.attrs),
+        ForeignItem::Static(i) => Some(&mut i.attrs),
+        ForeignItem::Type(i) => Some(&mut i.attrs),
+        ForeignItem::Macro(i) => Some(&mut i.attrs),
+        _ => None
+    }
+}
+
+/// Items in blocks are visited as items, so only let statements and expression statements are handled here.
+pub fn stmt_attributes(stmt: &mut Stmt) -> Option<&mut Vec<Attribute>> {
+    match stmt {
+        Stmt::Local(Local { attrs, .. }) => Some(attrs),
+        Stmt::Expr(expr) | Stmt::Semi(expr, _) => expr_attributes(expr),
+        Stmt::Item(_) => None
+    }
+}
+
+fn expr_attributes(expr: &mut Expr) -> Option<&mut Vec<Attribute>> {
+    match expr {
+        Expr::Array(e) => Some(&mut e.attrs),
+        Expr::Assign(e) => Some(&mut e.attrs),
+        Expr::AssignOp(e) => Some(&mut e.attrs),
+        Expr::Async(e) => Some(&mut e.attrs),
+        Expr::Await(e) => Some(&mut e.attrs),
+        Expr::Binary(e) => Some(&mut e.attrs),
+        Expr::Block(e) => Some(&mut e.attrs),
+        Expr::Box(e) => Some(&mut e.attrs),
+        Expr::Break(e) => Some(&mut e.attrs),
+        Expr::Call(e) => Some(&mut e.attrs),
+        Expr::Cast(e) => Some(&mut e.attrs),
+        Expr::Closure(e) => Some(&mut e.attrs),
+        Expr::Continue(e) => Some(&mut e.attrs),
+        Expr::Field(e) => Some(&mut e.attrs),
+        Expr::ForLoop(e) => Some(&mut e.attrs),
+        Expr::Group(e) => Some(&mut e.attrs),
+        Expr::If(e) => Some(&mut e.attrs),
+        Expr::Index(e) => Some(&mut e.attrs),
+        Expr::Let(e) => Some(&mut e.attrs),
+        Expr::Lit(e) => Some(&mut e.attrs),
+        Expr::Loop(e) => Some(&mut e.attrs),
+        Expr::Macro(e) => Some(&mut e.attrs),
+        Expr::Match(e) => Some(&mut e.attrs),
+        Expr::MethodCall(e) => Some(&mut e.attrs),
+        Expr::Paren(e) => Some(&mut e.attrs),
+        Expr::Path(e) => Some(&mut e.attrs),
+        Expr::Range(e) => Some(&mut e.attrs),
+        Expr::Reference(e) => Some(&mut e.attrs),
+        Expr::Repeat(e) => Some(&mut e.attrs),
+        Expr::Return(e) => Some(&mut e.attrs),
+        Expr::Struct(e) => Some(&mut e.attrs),
+        Expr::Try(e) => Some(&mut e.attrs),
+        Expr::TryBlock(e) => Some(&mut e.attrs),
+        Expr::Tuple(e) => Some(&mut e.attrs),
+        Expr::Type(e) => Some(&mut e.attrs),
+        Expr::Unary(e) => Some(&mut e.attrs),
+        Expr::Unsafe(e) => Some(&mut e.attrs),
+        Expr::While(e) => Some(&mut e.attrs),
+        Expr::Yield(e) => Some(&mut e.attrs),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::quote;
+    use syn::{parse_quote, Item};
+    use crate::extract::{attribute_args_tokens, extract_attribute_from_item};
+    use crate::matcher::AttributeMatcher;
+
+    fn answer() -> AttributeMatcher {
+        AttributeMatcher::new(parse_quote! { answer })
+    }
+
+    #[test]
+    fn attribute_on_method_is_found() {
+        let mut item: Item = parse_quote! {
+            impl S {
+                fn first() {}
+
+                #[inline]
+                #[answer(42)]
+                fn second() {}
+            }
+        };
+        let (attribute, tokens) = extract_attribute_from_item(&answer(), &mut item);
+
+        assert_eq!(attribute.tokens.to_string(), "(42)");
+        assert_eq!(tokens.to_string(), quote! { #[inline] fn second() {} }.to_string());
+        assert_eq!(quote! { #item }.to_string(), quote! { impl S { fn first() {} #[inline] fn second() {} } }.to_string());
+    }
+
+    #[test]
+    fn attribute_on_statement_is_found() {
+        let mut item: Item = parse_quote! {
+            fn f() {
+                #[answer]
+                let x = 1;
+            }
+        };
+        let (_, tokens) = extract_attribute_from_item(&answer(), &mut item);
+
+        assert_eq!(tokens.to_string(), quote! { let x = 1; }.to_string());
+    }
+
+    #[test]
+    fn attribute_on_verbatim_item_is_found() {
+        let mut item: Item = parse_quote! {
+            #[answer]
+            const ANSWER: usize;
+        };
+        assert!(matches!(item, Item::Verbatim(_)));
+
+        let (_, tokens) = extract_attribute_from_item(&answer(), &mut item);
+        assert_eq!(tokens.to_string(), quote! { const ANSWER: usize; }.to_string());
+    }
+
+    #[test]
+    fn args_of_all_delimiters_are_passed() {
+        for item in [
+            quote! { #[answer(T: Clone + 'static)] struct S; },
+            quote! { #[answer[T: Clone + 'static]] struct S; },
+            quote! { #[answer{T: Clone + 'static}] struct S; },
+        ] {
+            let (attribute, _) = extract_attribute_from_item(&answer(), &mut syn::parse2(item).unwrap());
+            assert_eq!(attribute_args_tokens(&attribute).unwrap().to_string(), "T : Clone + 'static");
+        }
+    }
+
+    #[test]
+    fn name_value_args_are_rejected() {
+        let mut item: Item = parse_quote! {
+            #[answer = "42"]
+            struct S;
+        };
+        let (attribute, _) = extract_attribute_from_item(&answer(), &mut item);
+
+        assert_eq!(attribute_args_tokens(&attribute).err().unwrap().to_string(), "key-value macro attributes are not supported");
+    }
+}
@@ -1,11 +1,53 @@
-use quote::__private::Ident;
+use std::panic::AssertUnwindSafe;
 use quote::quote;
 use syn::__private::TokenStream2;
-use syn::{Attribute, AttributeArgs, Item, Path};
-pub use attributes::proc_macro_attribute2;
+use syn::{DeriveInput, Item};
+use syn::parse::{Parse, Parser};
+use syn::parse_macro_input::ParseMacroInput;
+use macro_test_runtime::{panic_message, read_cases, write_case};
+use crate::compare::compare_token_streams;
+use crate::diff::{equivalence_report, mismatch_report};
+use crate::expected_error::check_expected_error;
+use crate::extract::{attribute_args_tokens, extract_attribute_from_item, missing_attribute_message, try_extract_attribute_from_item};
+use crate::minimize::reduce;
+use crate::panic::{catch_panic_silently, without_panic_output};
+use crate::pattern::{match_token_streams, printable_expectation};
+use crate::snapshot::assert_snapshot;
+pub use crate::compare::{ExpansionFailure, ExpansionMismatch, TokenMismatch};
+pub use crate::equivalence::Inputs;
+pub use crate::expected_error::ErrorMessage;
+pub use crate::matcher::AttributeMatcher;
+pub use crate::minimize::{FailureCondition, MinimizedFailure};
+pub use crate::panic::ImplementationPanic;
+pub use crate::pattern::Bindings;
+pub use crate::property::{ItemGenerator, ItemKind, PropertyFailure, PropertyTest, Violation};
+pub use crate::scratch::{assert_compiles, assert_runs};
+pub use crate::snapshot::BLESS_VARIABLE;
+pub use macro_test_runtime::{parse_item, panic_to_compile_error, proc_macro_attribute2, proc_macro_derive2, proc_macro_fn2};
+pub use macro_test_runtime::{record_invocation, recording_enabled, RECORD_VARIABLE};
+
+mod compare;
+mod diff;
+mod equivalence;
+mod expected_error;
+mod extract;
+mod matcher;
+mod minimize;
+mod panic;
+mod pattern;
+mod property;
+mod scratch;
+mod snapshot;
+mod temp_dir;
+
+// the generated code and the macros of this crate use these crates through macro_test, so users
+// don't need to depend on (or import) them
+pub use ::proc_macro2;
+pub use ::quote;
+pub use ::syn;
 
 /// This macro checks if an item with an attribute to test generates the
-/// expected token stream. Requires the import of macro-test::compare_implementations.
+/// expected token stream.
 /// This only works if your attributes uses the 'proc_macro_attribute2' attribute.
 /// Explanation below.
 ///
@@ -38,112 +80,977 @@ pub use attributes::proc_macro_attribute2;
 /// ```
 ///
 /// Currently, a check is created whether the attribute ('create_the_answer' in this case)
-/// creates the same token stream as the input of expected. Both token streams are turned
-/// into strings and are checked for equality.
+/// creates the same token stream as the input of expected. Both token streams are compared
+/// token tree by token tree, ignoring spans and whitespace.
 ///
 /// 'crate::my_attribute : create_the_answer' tells where your attribute is and what its named.
 /// The single colon is crucial because the path to the testable code will be in
-/// 'crate::my_attribute::implementation::create_the_answer'. This implementation module
-/// is created by 'proc_macro_attribute2'.
+/// 'crate::my_attribute::create_the_answer::implementation'. This module is created by
+/// 'proc_macro_attribute2' for every annotated function.
+///
+/// If the implementation module got another name with the 'module' option of 'proc_macro_attribute2',
+/// pass it after the attribute: 'crate::my_attribute : create_the_answer, module: imp, item: ...'.
+///
+/// The attribute doesn't need to be on the item itself. If it is on a nested node, like a method in an
+/// impl block, a trait item, a field, an enum variant or a statement in a function, the implementation
+/// gets this node and 'expected' is compared with the expansion of the node only.
+///
+/// # Placeholders
+/// Generated identifiers which can't be predicted are matched with placeholders in 'expected': '$_' matches
+/// any token tree, '$..' any sequence of token trees and '$name:ident' any identifier, which has to be the
+/// same wherever the name appears. Write '$$' for a '$' of the expansion. The bound identifiers are passed to
+/// the optional 'bindings' closure for further checks:
+///
+/// ``` text
+/// assert_attribute_implementation_as_expected!(
+///             crate::my_attribute : create_the_answer,
+///             item: {
+///                 #[create_the_answer]
+///                 struct S;
+///             }
+///
+///             expected: {
+///                 struct S;
+///
+///                 impl S {
+///                     fn get_the_answer() -> usize { $helper:ident() }
+///                 }
+///
+///                 fn $helper:ident() -> usize { $.. }
+///             },
+///             bindings: |bindings| assert!(bindings["helper"].to_string().starts_with("__S_"))
+///         )
+/// ```
+///
+/// # Snapshots
+/// Instead of 'expected', the expansion can be compared with a stored snapshot:
+///
+/// ``` text
+/// assert_attribute_implementation_as_expected!(
+///             crate::my_attribute : create_the_answer,
+///             item: {
+///                 #[create_the_answer]
+///                 struct S;
+///             }
+///
+///             snapshot
+///         )
+/// ```
+///
+/// The snapshot is stored pretty-printed in 'snapshots/<test name>.snap' of the crate directory. A test
+/// containing more than one snapshot has to name them: 'snapshot: "answer_for_enums"'.
+/// Missing or differing snapshots are written to '<name>.snap.new' and fail the test. Review the pending
+/// snapshot and rename it, or run the tests with MACRO_TEST_BLESS=1 to overwrite the stored snapshots.
+///
+/// # Errors
+/// Failure paths are tested with 'expected_error'. The test checks that the implementation returned an
+/// error or emitted a 'compile_error!' whose message contains the given text:
+///
+/// ``` text
+/// assert_attribute_implementation_as_expected!(
+///             crate::my_attribute : create_the_answer,
+///             item: {
+///                 #[create_the_answer]
+///                 struct S {
+///                     bad: usize,
+///                 }
+///             }
+///
+///             expected_error: "fields must not be named", span: bad
+///         )
+/// ```
+///
+/// With 'expected_error: regex "^fields .*$"' the message has to match the regex instead. The optional
+/// 'span' checks that the error points at (or covers) the given token of the item.
+///
+/// # Attribute paths and cfg_attr
+/// The attribute is found by its whole path, so '#[my_macros::create_the_answer]' isn't found unless it
+/// is listed in 'aliases'. With 'cfg', 'cfg_attr' attributes are expanded for the given enabled cfgs
+/// before the attribute is searched. Both come after the module (if any) and before the item:
+///
+/// ``` text
+/// assert_attribute_implementation_as_expected!(
+///             crate::my_attribute : create_the_answer,
+///             aliases: [my_macros::create_the_answer],
+///             cfg: [test, feature = "answer"],
+///             item: {
+///                 #[cfg_attr(test, my_macros::create_the_answer)]
+///                 struct S;
+///             }
+///
+///             expected: { ... }
+///         )
+/// ```
 #[macro_export]
 macro_rules! assert_attribute_implementation_as_expected {
-    ($base_path:path : $attr:ident, item: {$item:item}  expected: {$($expected:tt)*}) => {
+    (@matcher $attr:ident $(, aliases: [$($alias:path),* $(,)?])? $(, cfg: [$($cfg:meta),* $(,)?])?) => {
+        $crate::AttributeMatcher::new($crate::syn::parse_quote!($attr))
+            $($(.alias($crate::syn::parse_quote!($alias)))*)?
+            $(.cfgs([$($crate::syn::parse_quote!($cfg)),*]))?
+    };
+    (@error $base_path:path : $attr:ident, module: $module:ident, $(aliases: $aliases:tt,)? $(cfg: $cfgs:tt,)? item: {$item:item} message: $message:expr $(, span: $span:tt)?) => {
+        {
+            use $base_path :: {$module :: implementation};
+
+            let matcher = $crate::assert_attribute_implementation_as_expected!(@matcher $attr $(, aliases: $aliases)? $(, cfg: $cfgs)?);
+            // parsed from a string, as only then the tokens have distinct spans
+            let item = $crate::syn::parse_str::<$crate::syn::Item>(::core::stringify!($item)).unwrap();
+            let span_token = [$(::core::stringify!($span))?].first().copied();
+            $crate::compare_implementation_errors(|args, ts| implementation(args, ts), matcher, item, $message, span_token)
+        }
+    };
+    ($base_path:path : $attr:ident, module: $module:ident, $(aliases: $aliases:tt,)? $(cfg: $cfgs:tt,)? item: {$item:item}  expected_error: regex $pattern:literal $(, span: $span:tt)?) => {
+        $crate::assert_attribute_implementation_as_expected!(
+            @error $base_path : $attr, module: $module, $(aliases: $aliases,)? $(cfg: $cfgs,)? item: {$item} message: $crate::ErrorMessage::Regex($pattern) $(, span: $span)?
+        )
+    };
+    ($base_path:path : $attr:ident, module: $module:ident, $(aliases: $aliases:tt,)? $(cfg: $cfgs:tt,)? item: {$item:item}  expected_error: $message:literal $(, span: $span:tt)?) => {
+        $crate::assert_attribute_implementation_as_expected!(
+            @error $base_path : $attr, module: $module, $(aliases: $aliases,)? $(cfg: $cfgs,)? item: {$item} message: $crate::ErrorMessage::Substring($message) $(, span: $span)?
+        )
+    };
+    ($base_path:path : $attr:ident, module: $module:ident, $(aliases: $aliases:tt,)? $(cfg: $cfgs:tt,)? item: {$item:item}  snapshot $(: $name:literal)?) => {
+        {
+            use $base_path :: {$module :: implementation};
+
+            let matcher = $crate::assert_attribute_implementation_as_expected!(@matcher $attr $(, aliases: $aliases)? $(, cfg: $cfgs)?);
+            let item = $crate::syn::parse2::<$crate::syn::Item>($crate::quote::quote! { $item }).unwrap();
+            let snapshot_dir = ::std::path::Path::new(::core::concat!(::core::env!("CARGO_MANIFEST_DIR"), "/snapshots"));
+            let name = [$($name)?].first().copied();
+            $crate::snapshot_implementations(|args, ts| implementation(args, ts), matcher, item, snapshot_dir, name)
+        }
+    };
+    ($base_path:path : $attr:ident, module: $module:ident, $(aliases: $aliases:tt,)? $(cfg: $cfgs:tt,)? item: {$item:item}  expected: {$($expected:tt)*} $(, bindings: |$bindings:ident| $check:expr)?) => {
+        {
+            use $base_path :: {$module :: implementation};
+
+            let matcher = $crate::assert_attribute_implementation_as_expected!(@matcher $attr $(, aliases: $aliases)? $(, cfg: $cfgs)?);
+            let item = $crate::syn::parse2::<$crate::syn::Item>($crate::quote::quote! { $item }).unwrap();
+            // quote! would mark every punct as Alone, the stringified tokens keep their spacing
+            let expected_ts: $crate::proc_macro2::TokenStream = ::core::stringify!($($expected)*).parse().unwrap();
+            $(let $bindings: $crate::Bindings =)? $crate::compare_implementations(|args, ts| implementation(args, ts), matcher, item, expected_ts);
+            $($check;)?
+        }
+    };
+    ($base_path:path : $attr:ident, module: $($rest:tt)*) => {
+        ::core::compile_error!(
+            "expected 'module: <ident>,' optionally followed by 'aliases: [..],' and 'cfg: [..],', \
+            then 'item: {..}' and 'expected: {..}' (optionally followed by ', bindings: |..| ..'), 'snapshot' or 'expected_error: ..'"
+        )
+    };
+    // the module defaults to the name of the attribute
+    ($base_path:path : $attr:ident, $($rest:tt)*) => {
+        $crate::assert_attribute_implementation_as_expected!($base_path : $attr, module: $attr, $($rest)*)
+    };
+}
+
+/// This macro checks if the expansion of an attribute is valid Rust code which type-checks. The expansion
+/// is written into a temporary crate, which is checked with 'cargo check --offline'. The crate has no
+/// dependencies except std, so everything the expansion refers to has to be defined in the optional prelude:
+///
+/// ``` text
+/// assert_expansion_compiles!(
+///             crate::my_attribute : create_the_answer,
+///             prelude: {
+///                 pub trait Answer {
+///                     fn get_the_answer() -> usize;
+///                 }
+///             }
+///             item: {
+///                 #[create_the_answer]
+///                 struct S;
+///             }
+///         )
+/// ```
+///
+/// If the code doesn't compile, the test fails with the compiler errors and the checked code. Like
+/// 'assert_attribute_implementation_as_expected', a renamed implementation module is passed with
+/// 'module: imp,' after the attribute. As this runs cargo, it is much slower than comparing token streams.
+#[macro_export]
+macro_rules! assert_expansion_compiles {
+    ($base_path:path : $attr:ident, module: $module:ident, $(prelude: {$($prelude:tt)*})? item: {$item:item}) => {
+        {
+            use $base_path :: {$module :: implementation};
+
+            let matcher = $crate::AttributeMatcher::new($crate::syn::parse_quote!($attr));
+            let item = $crate::syn::parse2::<$crate::syn::Item>($crate::quote::quote! { $item }).unwrap();
+            let prelude = $crate::quote::quote! { $($($prelude)*)? };
+            $crate::check_implementation_compiles(|args, ts| implementation(args, ts), matcher, item, prelude)
+        }
+    };
+    ($base_path:path : $attr:ident, module: $($rest:tt)*) => {
+        ::core::compile_error!("expected 'module: <ident>,' followed by an optional 'prelude: {..}' and 'item: {..}'")
+    };
+    // the module defaults to the name of the attribute
+    ($base_path:path : $attr:ident, $($rest:tt)*) => {
+        $crate::assert_expansion_compiles!($base_path : $attr, module: $attr, $($rest)*)
+    };
+}
+
+/// This macro checks the runtime behavior of the expansion of an attribute. The expansion is written into a
+/// temporary crate together with the optional prelude and a main function containing the test body. The
+/// crate is built with 'cargo build --offline' and run:
+///
+/// ``` text
+/// assert_expansion_runs!(
+///             crate::my_attribute : create_the_answer,
+///             item: {
+///                 #[create_the_answer]
+///                 struct S;
+///             }
+///             test: {
+///                 assert_eq!(S::get_the_answer(), 42);
+///             }
+///         )
+/// ```
+///
+/// If the test body panics, the test fails with the panic message and the executed code. Like in
+/// 'assert_expansion_compiles', the crate has no dependencies except std.
+#[macro_export]
+macro_rules! assert_expansion_runs {
+    ($base_path:path : $attr:ident, module: $module:ident, $(prelude: {$($prelude:tt)*})? item: {$item:item} test: {$($test:tt)*}) => {
+        {
+            use $base_path :: {$module :: implementation};
+
+            let matcher = $crate::AttributeMatcher::new($crate::syn::parse_quote!($attr));
+            let item = $crate::syn::parse2::<$crate::syn::Item>($crate::quote::quote! { $item }).unwrap();
+            let prelude = $crate::quote::quote! { $($($prelude)*)? };
+            let test_body = $crate::quote::quote! { $($test)* };
+            $crate::check_implementation_runs(|args, ts| implementation(args, ts), matcher, item, prelude, test_body)
+        }
+    };
+    ($base_path:path : $attr:ident, module: $($rest:tt)*) => {
+        ::core::compile_error!("expected 'module: <ident>,' followed by an optional 'prelude: {..}', 'item: {..}' and 'test: {..}'")
+    };
+    // the module defaults to the name of the attribute
+    ($base_path:path : $attr:ident, $($rest:tt)*) => {
+        $crate::assert_expansion_runs!($base_path : $attr, module: $attr, $($rest)*)
+    };
+}
+
+/// This macro throws many generated items at an attribute implementation and checks that it never panics,
+/// that its expansion always parses as a Rust file and that the expansion contains the identifier of the
+/// item. The items are structs, enums, functions and traits with generics, lifetimes, where clauses,
+/// attributes, visibilities and raw identifiers, each carrying the tested attribute:
+///
+/// ``` text
+/// assert_attribute_properties!(
+///             crate::my_attribute : create_the_answer,
+///             args: { value = 42 },
+///             kinds: [Struct, TupleStruct, UnitStruct],
+///             cases: 500,
+///         )
+/// ```
+///
+/// All settings are optional: 'args' are the arguments of the attribute (none by default), 'kinds' restricts
+/// the generated items (all kinds by default) and 'cases' is the number of items (256 by default). A failing
+/// item is shrunk to a minimal failing item before it is reported. The items are generated from a fixed seed,
+/// so every run checks the same items.
+#[macro_export]
+macro_rules! assert_attribute_properties {
+    ($base_path:path : $attr:ident, module: $module:ident $(, args: {$($args:tt)*})? $(, kinds: [$($kind:ident),*])? $(, cases: $cases:expr)? $(,)?) => {
+        {
+            use $base_path :: {$module :: implementation};
+
+            let test = $crate::PropertyTest::new($crate::syn::parse_quote!($attr))
+                $(.attribute_args($crate::quote::quote! { $($args)* }))?
+                $(.kinds(&[$($crate::ItemKind::$kind),*]))?
+                $(.cases($cases))?;
+            $crate::check_implementation_properties(|args, ts| implementation(args, ts), test)
+        }
+    };
+    ($base_path:path : $attr:ident, module: $($rest:tt)*) => {
+        ::core::compile_error!("expected 'module: <ident>' followed by optional 'args: {..}', 'kinds: [..]' and 'cases: <n>'")
+    };
+    // the module defaults to the name of the attribute
+    ($base_path:path : $attr:ident $(, $($rest:tt)*)?) => {
+        $crate::assert_attribute_properties!($base_path : $attr, module: $attr $(, $($rest)*)?)
+    };
+}
+
+/// This macro reduces an item on which an attribute implementation fails to a minimal item, and prints an
+/// 'assert_attribute_implementation_as_expected!' call with it which is ready to be pasted into a test:
+///
+/// ``` text
+/// minimize_attribute_failure!(
+///             crate::my_attribute : create_the_answer,
+///             item: {
+///                 #[create_the_answer]
+///                 struct S {
+///                     // a large item from a bug report
+///                 }
+///             }
+///             failure: panic
+///         )
+/// ```
+///
+/// The failure is 'panic', 'unparsable_expansion' (the expansion doesn't parse as a Rust file) or
+/// 'mismatch(|item| ...)' with a closure computing the expected expansion of an item, e.g. with the previous
+/// version of the implementation. The reduced item has to keep this failure. The macro returns the
+/// MinimizedFailure, so it can also be used in assertions.
+#[macro_export]
+macro_rules! minimize_attribute_failure {
+    (@failure panic) => { $crate::FailureCondition::Panic };
+    (@failure unparsable_expansion) => { $crate::FailureCondition::UnparsableExpansion };
+    (@failure mismatch($expected:expr)) => { $crate::FailureCondition::Mismatch(::std::boxed::Box::new($expected)) };
+    ($base_path:path : $attr:ident, module: $module:ident, item: {$item:item} failure: $($failure:tt)+) => {
+        {
+            use $base_path :: {$module :: implementation};
+
+            let matcher = $crate::AttributeMatcher::new($crate::syn::parse_quote!($attr));
+            let item = $crate::syn::parse2::<$crate::syn::Item>($crate::quote::quote! { $item }).unwrap();
+            let failure = $crate::minimize_attribute_failure!(@failure $($failure)+);
+            let minimized = $crate::minimize_implementation_failure(|args, ts| implementation(args, ts), matcher, item, failure);
+
+            let module = [::core::stringify!($module)].into_iter().find(|module| *module != ::core::stringify!($attr));
+            let base_path = ::core::stringify!($base_path).replace(" :: ", "::");
+            ::std::println!("{}", minimized.test_case(&base_path, ::core::stringify!($attr), module));
+            minimized
+        }
+    };
+    ($base_path:path : $attr:ident, module: $($rest:tt)*) => {
+        ::core::compile_error!("expected 'module: <ident>,' followed by 'item: {..}' and 'failure: ..'")
+    };
+    // the module defaults to the name of the attribute
+    ($base_path:path : $attr:ident, $($rest:tt)*) => {
+        $crate::minimize_attribute_failure!($base_path : $attr, module: $attr, $($rest)*)
+    };
+}
+
+/// This macro checks if a derive macro generates the expected token stream for a given
+/// struct, enum or union.
+/// This only works if your derive macro uses the 'proc_macro_derive2' attribute.
+///
+/// ``` text
+/// assert_derive_implementation_as_expected!(
+///             crate::my_derive : derive_answer,
+///             item: {
+///                 #[derive(Answer)]
+///                 struct S;
+///             }
+///
+///             expected: {
+///                 impl S {
+///                     fn get_the_answer() -> usize {
+///                         42
+///                     }
+///                 }
+///             }
+///         )
+/// ```
+///
+/// 'derive_answer' is the name of the function annotated with 'proc_macro_derive2', not the
+/// name of the derive macro. Like rustc, the harness removes the '#[derive(...)]' attributes
+/// from the item before it is passed to the implementation. Only the generated impls are
+/// compared, as derive macros do not replace the item they are applied to.
+#[macro_export]
+macro_rules! assert_derive_implementation_as_expected {
+    ($base_path:path : $derive:ident, item: {$item:item}  expected: {$($expected:tt)*} $(, bindings: |$bindings:ident| $check:expr)?) => {
+        {
+            use $base_path :: {$derive :: implementation};
+
+            let item = $crate::syn::parse2::<$crate::syn::DeriveInput>($crate::quote::quote! { $item }).unwrap();
+            let expected_ts: $crate::proc_macro2::TokenStream = ::core::stringify!($($expected)*).parse().unwrap();
+            $(let $bindings: $crate::Bindings =)? $crate::compare_derive_implementations(|item| implementation(item), item, expected_ts);
+            $($check;)?
+        }
+    }
+}
+
+/// This macro checks if a function-like macro generates the expected token stream for a given
+/// input.
+/// This only works if your macro uses the 'proc_macro_fn2' attribute.
+///
+/// ``` text
+/// assert_function_macro_implementation_as_expected!(
+///             crate::my_macro : answer_fn,
+///             input: {
+///                 get_the_answer
+///             }
+///
+///             expected: {
+///                 fn get_the_answer() -> usize {
+///                     42
+///                 }
+///             }
+///         )
+/// ```
+///
+/// The input is everything that would be written between the delimiters of the macro
+/// invocation ('answer_fn!(get_the_answer)' in this case). It is parsed into the input type
+/// of the implementation, just like the generated macro does it.
+#[macro_export]
+macro_rules! assert_function_macro_implementation_as_expected {
+    ($base_path:path : $macro_fn:ident, input: {$($input:tt)*}  expected: {$($expected:tt)*} $(, bindings: |$bindings:ident| $check:expr)?) => {
+        $crate::assert_function_macro_implementation_as_expected!(
+            $base_path : $macro_fn, module: $macro_fn, input: {$($input)*} expected: {$($expected)*} $(, bindings: |$bindings| $check)?
+        )
+    };
+    ($base_path:path : $macro_fn:ident, module: $module:ident, input: {$($input:tt)*}  expected: {$($expected:tt)*} $(, bindings: |$bindings:ident| $check:expr)?) => {
         {
-            use $base_path :: {implementation :: $attr};
+            use $base_path :: {$module :: implementation};
 
-            let ident = syn::parse2::<syn::Ident>(quote::quote! {$attr}).unwrap();
-            let item = syn::parse2::<syn::Item>(quote::quote! { $item }).unwrap();
-            let expected_ts = quote::quote! { $($expected)* };
-            compare_implementations(|args, ts| $attr(args, ts), ident, item, expected_ts)
+            let input = $crate::quote::quote! { $($input)* };
+            let expected_ts: $crate::proc_macro2::TokenStream = ::core::stringify!($($expected)*).parse().unwrap();
+            $(let $bindings: $crate::Bindings =)? $crate::compare_function_macro_implementations(|input| implementation(input), input, expected_ts);
+            $($check;)?
         }
     }
 }
 
-pub fn compare_implementations(
-    implementor: fn(AttributeArgs, TokenStream2) -> TokenStream2,
-    attribute_ident: Ident,
+/// The return types an implementation may have. Implemented for TokenStream2 and
+/// syn::Result<TokenStream2>, which are the types accepted by the attributes of this crate.
+pub trait ImplementationOutput {
+    fn into_result(self) -> syn::Result<TokenStream2>;
+}
+
+impl ImplementationOutput for TokenStream2 {
+    fn into_result(self) -> syn::Result<TokenStream2> {
+        Ok(self)
+    }
+}
+
+impl ImplementationOutput for syn::Result<TokenStream2> {
+    fn into_result(self) -> syn::Result<TokenStream2> {
+        self
+    }
+}
+
+/// Compares the expansion of an attribute implementation with the expectation. The attribute arguments
+/// are the tokens rustc would pass to the macro, see 'attribute_args_tokens'. They are parsed into the first
+/// parameter type of the implementation (AttributeArgs or any type implementing syn::parse::Parse), like
+/// 'parse_macro_input!' does it. If they can't be parsed, the expansion is the parse error as compile error.
+/// The item is parsed into the second parameter type with 'parse_item'. If the implementation returns an
+/// error or the item has the wrong kind, the expansion is the compile error followed by the unchanged item.
+/// This is exactly what the code generated by 'proc_macro_attribute2' does. If the implementation panics,
+/// the test fails with the panic message, the attribute arguments and the item.
+///
+/// The attribute is searched with an AttributeMatcher, or just by its name if an Ident is given.
+///
+/// The expectation may contain placeholders, see 'match_token_streams'. The identifiers bound by them are
+/// printed and returned.
+pub fn compare_implementations<A: ParseMacroInput, I: Parse + 'static, O: ImplementationOutput>(
+    implementor: fn(A, I) -> O,
+    attribute: impl Into<AttributeMatcher>,
+    item: Item,
+    expectation: TokenStream2,
+) -> Bindings {
+    match try_compare_implementations(implementor, attribute, item, expectation) {
+        Ok(bindings) => report_bindings(bindings),
+        Err(failure) => panic!("{}", failure)
+    }
+}
+
+/// Like 'compare_implementations', but returns the mismatch, the panic of the implementation, a missing
+/// attribute or an invalid item instead of panicking, and the bindings of the placeholders if the expansion
+/// matches. This allows to build own assertions, to collect several failures or to report them differently.
+pub fn try_compare_implementations<A: ParseMacroInput, I: Parse + 'static, O: ImplementationOutput>(
+    implementor: fn(A, I) -> O,
+    attribute: impl Into<AttributeMatcher>,
     mut item: Item,
     expectation: TokenStream2,
+) -> Result<Bindings, ExpansionFailure> {
+    let matcher = attribute.into();
+    let (attribute, target) = try_extract_attribute_from_item(&matcher, &mut item)
+        .map_err(ExpansionFailure::InvalidItem)?
+        .ok_or_else(|| ExpansionFailure::AttributeNotFound(missing_attribute_message(&matcher, &item)))?;
+    let attribute_args = attribute_args_tokens(&attribute);
+    let implementation = expand_attribute_catching_panics(implementor, attribute_args.clone(), target, &item)
+        .map_err(ExpansionFailure::Panic)?;
+
+    match_token_streams(&implementation, &expectation).map_err(|mismatch| ExpansionFailure::Mismatch(Box::new(ExpansionMismatch {
+        actual: implementation,
+        expected: expectation,
+        mismatch,
+        attribute_args: attribute_args.unwrap_or_default(),
+        item,
+    })))
+}
+
+/// Checks that an attribute implementation fails with the expected error, see
+/// 'assert_attribute_implementation_as_expected'. Errors returned by the implementation and 'compile_error!'
+/// invocations in its expansion are both accepted. If a span token is given, the error has to cover a
+/// token of the item written like it. This requires an item parsed from a string, e.g. with syn::parse_str.
+pub fn compare_implementation_errors<A: ParseMacroInput, I: Parse + 'static, O: ImplementationOutput>(
+    implementor: fn(A, I) -> O,
+    matcher: AttributeMatcher,
+    item: Item,
+    message: ErrorMessage,
+    span_token: Option<&str>,
+) {
+    let item_tokens = quote! {#item};
+    let implementation = attribute_expansion(implementor, matcher, item);
+
+    if let Err(report) = check_expected_error(&implementation, &item_tokens, message, span_token) {
+        panic!("{}", report)
+    }
+}
+
+/// Checks that the expansion of an attribute implementation compiles together with the prelude, see
+/// 'assert_expansion_compiles'. The expansion is created like in 'compare_implementations'.
+pub fn check_implementation_compiles<A: ParseMacroInput, I: Parse + 'static, O: ImplementationOutput>(
+    implementor: fn(A, I) -> O,
+    matcher: AttributeMatcher,
+    item: Item,
+    prelude: TokenStream2,
+) {
+    let implementation = attribute_expansion(implementor, matcher, item);
+    assert_compiles(&implementation, &prelude)
+}
+
+/// Runs the test body against the expansion of an attribute implementation, see 'assert_expansion_runs'.
+/// The expansion is created like in 'compare_implementations'.
+pub fn check_implementation_runs<A: ParseMacroInput, I: Parse + 'static, O: ImplementationOutput>(
+    implementor: fn(A, I) -> O,
+    matcher: AttributeMatcher,
+    item: Item,
+    prelude: TokenStream2,
+    test_body: TokenStream2,
 ) {
-    let attribute = extract_attribute_from_item(&attribute_ident, &mut item);
-    let attribute_args = transform_attribute_to_attribute_args(attribute);
-    let implementation = (implementor)(attribute_args, quote! {#item});
-    let remove_whitespace = |s: String| s.chars()
-        .filter(|c| !c.is_whitespace())
-        .collect::<String>();
-    assert_eq!(remove_whitespace(implementation.to_string()), remove_whitespace(expectation.to_string()))
+    let implementation = attribute_expansion(implementor, matcher, item);
+    assert_runs(&implementation, &prelude, &test_body)
+}
+
+/// Checks invariants of an attribute implementation for generated items, see 'assert_attribute_properties'
+/// and 'PropertyTest'. The expansions are created like in 'compare_implementations'. If a property is
+/// violated, the test fails with the shrunk item, its expansion and the seed.
+pub fn check_implementation_properties<A: ParseMacroInput, I: Parse + 'static, O: ImplementationOutput>(
+    implementor: fn(A, I) -> O,
+    test: PropertyTest,
+) {
+    let matcher = test.matcher();
+    // the property test catches the original panic, so its payload is passed on unchanged
+    let expand = |item| try_attribute_expansion(implementor, matcher.clone(), item)
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic.payload));
+
+    if let Err(failure) = test.run(expand) {
+        panic!("{}", failure)
+    }
+}
+
+/// Compares the expansion of an attribute implementation with a stored snapshot, see
+/// 'assert_attribute_implementation_as_expected'. The expansion is created like in 'compare_implementations'.
+pub fn snapshot_implementations<A: ParseMacroInput, I: Parse + 'static, O: ImplementationOutput>(
+    implementor: fn(A, I) -> O,
+    matcher: AttributeMatcher,
+    item: Item,
+    snapshot_dir: &std::path::Path,
+    snapshot_name: Option<&str>,
+) {
+    let implementation = attribute_expansion(implementor, matcher, item);
+    assert_snapshot(&implementation, snapshot_dir, snapshot_name)
+}
+
+/// Replays the invocations of an attribute macro recorded with MACRO_TEST_RECORD. 'macro_dir' is the
+/// directory of the macro in the corpus, like 'corpus/answer'. Every recorded case is expanded with the
+/// current implementation like in 'compare_implementations'. If any expansion differs from the recorded
+/// output or the implementation panics, the test fails with a report of all differing cases.
+pub fn replay_corpus<A: ParseMacroInput, I: Parse + 'static, O: ImplementationOutput>(
+    macro_dir: &std::path::Path,
+    implementor: fn(A, I) -> O,
+) {
+    let cases = read_cases(macro_dir);
+
+    if cases.is_empty() {
+        panic!("The corpus {} contains no recorded cases", macro_dir.display());
+    }
+
+    let reports = cases.iter()
+        .filter_map(|case| {
+            let report = match expand_input(implementor, Ok(case.attribute_args.clone()), case.item.clone()) {
+                Ok(expansion) => compare_token_streams(&expansion, &case.output)
+                    .err()
+                    .map(|mismatch| mismatch_report(&expansion, &case.output, &mismatch))?,
+                Err(message) => format!("The implementation panicked: {}\n", message)
+            };
+            Some(format!("Recorded case {}:\n{}", case.dir.display(), report))
+        })
+        .collect::<Vec<_>>();
+
+    if !reports.is_empty() {
+        panic!("{} of {} recorded cases differ from the recorded output\n\n{}", reports.len(), cases.len(), reports.join("\n"))
+    }
+}
+
+/// Expands every input with the old and the new implementation of an attribute and compares the expansions
+/// structurally, like 'compare_implementations'. If any expansions differ, the test fails with a report of
+/// all differing inputs. A panic counts as difference, unless both implementations panic with the same message.
+pub fn assert_implementations_equivalent<A1, I1, O1, A2, I2, O2>(
+    old: fn(A1, I1) -> O1,
+    new: fn(A2, I2) -> O2,
+    inputs: Inputs,
+) where
+    A1: ParseMacroInput, I1: Parse + 'static, O1: ImplementationOutput,
+    A2: ParseMacroInput, I2: Parse + 'static, O2: ImplementationOutput,
+{
+    if inputs.is_empty() {
+        panic!("There are no inputs to compare the implementations with");
+    }
+
+    let reports = inputs.inputs.iter()
+        .filter_map(|input| {
+            let old_expansion = expand_input(old, input.attribute_args.clone(), input.target.clone());
+            let new_expansion = expand_input(new, input.attribute_args.clone(), input.target.clone());
+            let report = match (old_expansion, new_expansion) {
+                (Ok(old), Ok(new)) => compare_token_streams(&new, &old)
+                    .err()
+                    .map(|mismatch| equivalence_report(&old, &new, &mismatch))?,
+                (Err(old), Err(new)) if old == new => return None,
+                (Err(old), Err(new)) => format!("Both implementations panicked, the old one with '{}', the new one with '{}'\n", old, new),
+                (Err(old), Ok(_)) => format!("The old implementation panicked: {}\n", old),
+                (Ok(_), Err(new)) => format!("The new implementation panicked: {}\n", new),
+            };
+            Some(format!("{}\n{}", input.describe(), report))
+        })
+        .collect::<Vec<_>>();
+
+    if !reports.is_empty() {
+        panic!("{} of {} inputs expand differently\n\n{}", reports.len(), inputs.len(), reports.join("\n"))
+    }
+}
+
+/// Records the expansions of an attribute implementation into 'macro_dir' like MACRO_TEST_RECORD does it.
+/// Run it with the previous release of the implementation to save its outputs, then 'replay_corpus' checks
+/// the current implementation against them. Name-value attributes are skipped, as rustc never invokes the
+/// macro for them.
+pub fn record_corpus<A: ParseMacroInput, I: Parse + 'static, O: ImplementationOutput>(
+    macro_dir: &std::path::Path,
+    implementor: fn(A, I) -> O,
+    inputs: Inputs,
+) {
+    for input in &inputs.inputs {
+        if let Ok(attribute_args) = &input.attribute_args {
+            let expansion = expand_attribute(implementor, Ok(attribute_args.clone()), input.target.clone());
+            if let Err(e) = write_case(macro_dir, attribute_args, &input.target, &expansion) {
+                panic!("Could not record {} into {}: {}", input.description, macro_dir.display(), e)
+            }
+        }
+    }
+}
+
+/// Reduces an item on which the attribute implementation fails to a minimal item which still fails in the
+/// same way, see 'minimize_attribute_failure'. The item has to fail initially. The expansions are created like
+/// in 'compare_implementations', and panics of the implementation are not printed while the item is reduced.
+pub fn minimize_implementation_failure<A: ParseMacroInput, I: Parse + 'static, O: ImplementationOutput>(
+    implementor: fn(A, I) -> O,
+    matcher: AttributeMatcher,
+    item: Item,
+    failure: FailureCondition,
+) -> MinimizedFailure {
+    let fails = |candidate: &Item| {
+        let mut reduced = candidate.clone();
+        let (attribute, target) = match try_extract_attribute_from_item(&matcher, &mut reduced) {
+            Ok(Some(found)) => found,
+            _ => return false
+        };
+        let expansion = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            expand_attribute(implementor, attribute_args_tokens(&attribute), target)
+        }));
+
+        match (&failure, expansion) {
+            (FailureCondition::Panic, Err(_)) => true,
+            (FailureCondition::UnparsableExpansion, Ok(expansion)) => syn::parse2::<syn::File>(expansion).is_err(),
+            (FailureCondition::Mismatch(expected), Ok(expansion)) => compare_token_streams(&expansion, &expected(candidate)).is_err(),
+            _ => false
+        }
+    };
 
+    let minimized = without_panic_output(|| {
+        if !fails(&item) {
+            panic!("The item does not fail, {} is expected", failure.describe());
+        }
+        reduce(item, fails)
+    });
+
+    let expected = match &failure {
+        FailureCondition::Mismatch(expected) => Some(expected(&minimized)),
+        _ => None
+    };
+    MinimizedFailure { item: minimized, expected }
+}
+
+/// Expands the item like 'try_compare_implementations'. A panic of the implementation fails the test with
+/// the report of ImplementationPanic.
+fn attribute_expansion<A: ParseMacroInput, I: Parse + 'static, O: ImplementationOutput>(
+    implementor: fn(A, I) -> O,
+    matcher: AttributeMatcher,
+    item: Item,
+) -> TokenStream2 {
+    try_attribute_expansion(implementor, matcher, item).unwrap_or_else(|panic| panic!("{}", panic))
+}
+
+fn try_attribute_expansion<A: ParseMacroInput, I: Parse + 'static, O: ImplementationOutput>(
+    implementor: fn(A, I) -> O,
+    matcher: AttributeMatcher,
+    mut item: Item,
+) -> Result<TokenStream2, Box<ImplementationPanic>> {
+    let (attribute, target) = extract_attribute_from_item(&matcher, &mut item);
+    expand_attribute_catching_panics(implementor, attribute_args_tokens(&attribute), target, &item)
+}
+
+fn expand_attribute_catching_panics<A: ParseMacroInput, I: Parse + 'static, O: ImplementationOutput>(
+    implementor: fn(A, I) -> O,
+    attribute_args: syn::Result<TokenStream2>,
+    target: TokenStream2,
+    item: &Item,
+) -> Result<TokenStream2, Box<ImplementationPanic>> {
+    let args = attribute_args.as_ref().cloned().unwrap_or_default();
+
+    std::panic::catch_unwind(AssertUnwindSafe(|| expand_attribute(implementor, attribute_args, target)))
+        .map_err(|payload| Box::new(ImplementationPanic::new(payload, args, item.clone())))
 }
 
-fn extract_attribute_from_item(attribute_ident: &Ident, item: &mut Item) -> Attribute {
-    let attributes = get_attributes_from_item(item);
-    let attribute_index = attributes.iter()
-        .enumerate()
-        .find(|(_, a)| attribute_has_ident(a, attribute_ident))
-        .expect("Could not find expected attribute").0;
-    attributes.remove(attribute_index)
+/// Expands the input, a panic of the implementation is returned as its message without being printed.
+fn expand_input<A: ParseMacroInput, I: Parse + 'static, O: ImplementationOutput>(
+    implementor: fn(A, I) -> O,
+    attribute_args: syn::Result<TokenStream2>,
+    target: TokenStream2,
+) -> Result<TokenStream2, String> {
+    catch_panic_silently(|| expand_attribute(implementor, attribute_args, target))
+        .map_err(|payload| panic_message(payload.as_ref()))
 }
 
-fn get_attributes_from_item(item: &mut Item) -> &mut Vec<Attribute> {
-    match item {
-        Item::Const(i) => &mut i.attrs,
-        Item::Enum(i) => &mut i.attrs,
-        Item::ExternCrate(i) => &mut i.attrs,
-        Item::Fn(i) => &mut i.attrs,
-        Item::ForeignMod(i) => &mut i.attrs,
-        Item::Impl(i) => &mut i.attrs,
-        Item::Macro(i) => &mut i.attrs,
-        Item::Macro2(i) => &mut i.attrs,
-        Item::Mod(i) => &mut i.attrs,
-        Item::Static(i) => &mut i.attrs,
-        Item::Struct(i) => &mut i.attrs,
-        Item::Trait(i) => &mut i.attrs,
-        Item::TraitAlias(i) => &mut i.attrs,
-        Item::Type(i) => &mut i.attrs,
-        Item::Union(i) => &mut i.attrs,
-        Item::Use(i) => &mut i.attrs,
-        _ => panic!("Could not extract attributes")
+fn expand_attribute<A: ParseMacroInput, I: Parse + 'static, O: ImplementationOutput>(
+    implementor: fn(A, I) -> O,
+    attribute_args: syn::Result<TokenStream2>,
+    target: TokenStream2,
+) -> TokenStream2 {
+    match attribute_args.and_then(|attribute_args| A::parse.parse2(attribute_args)) {
+        Ok(attribute_args) => match parse_item::<I>(target.clone())
+            .and_then(|parsed_item| (implementor)(attribute_args, parsed_item).into_result()) {
+            Ok(implementation) => implementation,
+            Err(error) => {
+                let error = error.to_compile_error();
+                quote! {#error #target}
+            }
+        },
+        Err(error) => error.to_compile_error()
     }
 }
 
-fn attribute_has_ident(a: &Attribute, i: &Ident) -> bool {
-    *i == path_to_name(&a.path)
+pub fn compare_derive_implementations<O: ImplementationOutput>(
+    implementor: fn(DeriveInput) -> O,
+    mut item: DeriveInput,
+    expectation: TokenStream2,
+) -> Bindings {
+    item.attrs.retain(|a| !a.path.is_ident("derive"));
+    let implementation = expansion_or_compile_error((implementor)(item));
+    assert_token_streams_equal(implementation, expectation)
+}
+
+/// Compares the expansion of a function-like macro implementation with the expectation. If the input can't
+/// be parsed into the parameter type, the expansion is the parse error as compile error, like
+/// 'parse_macro_input!' in the code generated by 'proc_macro_fn2' does it.
+pub fn compare_function_macro_implementations<T: Parse, O: ImplementationOutput>(
+    implementor: fn(T) -> O,
+    input: TokenStream2,
+    expectation: TokenStream2,
+) -> Bindings {
+    let implementation = match syn::parse2::<T>(input) {
+        Ok(input) => expansion_or_compile_error((implementor)(input)),
+        Err(error) => error.to_compile_error()
+    };
+    assert_token_streams_equal(implementation, expectation)
+}
+
+fn expansion_or_compile_error<O: ImplementationOutput>(output: O) -> TokenStream2 {
+    output.into_result().unwrap_or_else(|error| error.to_compile_error())
 }
 
-fn path_to_name(p: &Path) -> String {
-    p.segments
-        .last()
-        .map(|seg| seg.ident.to_string())
-        .expect("The given path was not an identifier.")
+fn assert_token_streams_equal(implementation: TokenStream2, expectation: TokenStream2) -> Bindings {
+    match match_token_streams(&implementation, &expectation) {
+        Ok(bindings) => report_bindings(bindings),
+        Err(mismatch) => panic!("{}", mismatch_report(&implementation, &printable_expectation(&expectation), &mismatch))
+    }
 }
 
-fn transform_attribute_to_attribute_args(attribute: Attribute) -> AttributeArgs {
-    match attribute.parse_meta().unwrap() {
-        syn::Meta::List(list) => list.nested.into_iter().collect(),
-        _ => vec![]
+/// Prints the bindings of a passing test, they are shown with '--nocapture' or if a later assertion fails.
+fn report_bindings(bindings: Bindings) -> Bindings {
+    if !bindings.is_empty() {
+        println!("The expectation matched with {}", bindings);
     }
+    bindings
 }
 
 #[cfg(test)]
 mod tests {
-    pub mod implementation {
-        use syn::__private::TokenStream2;
-        use syn::AttributeArgs;
+    use quote::quote;
+    use syn::__private::TokenStream2;
+    use syn::{parse_quote, AttributeArgs, DeriveInput, Ident, ImplItemMethod, ItemStruct, LitInt, LitStr, Token};
+    use crate::{AttributeMatcher, Inputs, ItemKind, PropertyTest};
+    use crate::temp_dir::TempDir;
+    use syn::parse::{Parse, ParseStream};
+
+    pub struct AnswerOptions {
+        value: LitInt,
+    }
+
+    impl Parse for AnswerOptions {
+        fn parse(input: ParseStream) -> syn::Result<Self> {
+            let key = input.parse::<Ident>()?;
+            if key != "value" {
+                return Err(syn::Error::new(key.span(), "expected 'value'"));
+            }
+            input.parse::<Token![=]>()?;
+            Ok(AnswerOptions { value: input.parse()? })
+        }
+    }
 
-        pub fn bar(_attr: AttributeArgs, item: TokenStream2) -> TokenStream2 {
+    pub mod bar {
+        use super::*;
+
+        pub fn implementation(_attr: AttributeArgs, item: TokenStream2) -> TokenStream2 {
             item
         }
     }
 
-    #[test]
-    fn foo() {
-        use crate::compare_implementations;
+    pub mod derive_answer {
+        use super::*;
 
-        assert_attribute_implementation_as_expected!(
-            crate::tests : bar,
-            item: {
-                #[bar]
-                struct S {
+        pub fn implementation(item: DeriveInput) -> TokenStream2 {
+            let ident = &item.ident;
+            quote! {
+                impl #ident {
+                    pub fn get_answer() -> usize { 42 }
+                }
+            }
+        }
+    }
+
+    pub mod only_structs {
+        use super::*;
+
+        pub fn implementation(_attr: AttributeArgs, item: TokenStream2) -> syn::Result<TokenStream2> {
+            syn::parse2::<ItemStruct>(item.clone())
+                .map(|_| item)
+                .map_err(|e| syn::Error::new(e.span(), "only structs are supported"))
+        }
+    }
+
+    pub mod configurable_answer {
+        use super::*;
+
+        pub fn implementation(options: AnswerOptions, item: TokenStream2) -> TokenStream2 {
+            let item_struct = syn::parse2::<ItemStruct>(item).unwrap();
+            let ident = &item_struct.ident;
+            let value = &options.value;
+            quote! {
+                #item_struct
+
+                impl #ident {
+                    pub fn get_answer() -> usize { #value }
+                }
+            }
+        }
+    }
+
+    pub mod typed_configurable_answer {
+        use super::*;
+
+        pub fn implementation(options: AnswerOptions, item: ItemStruct) -> TokenStream2 {
+            let ident = &item.ident;
+            let value = &options.value;
+            quote! {
+                #item
+
+                impl #ident {
+                    pub fn get_answer() -> usize { #value }
+                }
+            }
+        }
+    }
+
+    pub mod checked_fields {
+        use super::*;
+
+        pub fn implementation(_attr: AttributeArgs, item: ItemStruct) -> syn::Result<TokenStream2> {
+            match item.fields.iter().find(|f| f.ident.as_ref().map(|i| i == "bad").unwrap_or(false)) {
+                Some(field) => Err(syn::Error::new_spanned(field, "fields must not be named 'bad'")),
+                None => Ok(quote! {#item})
+            }
+        }
+    }
+
+    pub mod answer_method {
+        use super::*;
+
+        pub fn implementation(_attr: AttributeArgs, mut method: ImplItemMethod) -> TokenStream2 {
+            method.block = syn::parse_quote! {{ 42 }};
+            quote! {#method}
+        }
+    }
+
+    pub mod bounds {
+        use super::*;
+
+        pub fn implementation(bounds: TokenStream2, item: ItemStruct) -> TokenStream2 {
+            let ident = &item.ident;
+            quote! {
+                #item
+
+                impl<T> #ident where #bounds {}
+            }
+        }
+    }
+
+    pub mod renamed_answer_module {
+        use super::*;
+
+        pub fn implementation(_attr: AttributeArgs, item: TokenStream2) -> TokenStream2 {
+            item
+        }
+    }
+
+    pub mod typed_answer {
+        use super::*;
+
+        pub fn implementation(_attr: AttributeArgs, item: ItemStruct) -> TokenStream2 {
+            let ident = &item.ident;
+            quote! {
+                #item
+
+                impl #ident {
+                    pub fn get_answer() -> usize { 42 }
+                }
+            }
+        }
+    }
+
+    pub mod private_answer {
+        use super::*;
+
+        pub fn implementation(_attr: AttributeArgs, item: ItemStruct) -> TokenStream2 {
+            let ident = &item.ident;
+            let private = quote::format_ident!("__{}_private_{:x}", ident, ident.to_string().len() * 0x3a9f);
+            quote! {
+                #item
+
+                struct #private;
+
+                impl #ident {
+                    fn private() -> #private { #private }
+                }
+            }
+        }
+    }
+
+    pub mod answer_fn {
+        use super::*;
+
+        pub fn implementation(name: Ident) -> TokenStream2 {
+            quote! {
+                fn #name() -> usize { 42 }
+            }
+        }
+    }
+
+    #[test]
+    fn foo() {
+        assert_attribute_implementation_as_expected!(
+            crate::tests : bar,
+            item: {
+                #[bar]
+                struct S {
                     foo: usize,
                 }
             }
@@ -155,4 +1062,727 @@ mod tests {
             }
         )
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn derive() {
+        assert_derive_implementation_as_expected!(
+            crate::tests : derive_answer,
+            item: {
+                #[derive(Answer)]
+                enum E {
+                    A,
+                    B,
+                }
+            }
+
+            expected: {
+                impl E {
+                    pub fn get_answer() -> usize { 42 }
+                }
+            }
+        )
+    }
+
+    pub mod parsed_tokens {
+        use super::*;
+
+        pub fn implementation(code: LitStr) -> TokenStream2 {
+            code.value().parse().unwrap()
+        }
+    }
+
+    #[test]
+    fn function_macro() {
+        assert_function_macro_implementation_as_expected!(
+            crate::tests : answer_fn,
+            input: {
+                get_answer
+            }
+
+            expected: {
+                fn get_answer() -> usize { 42 }
+            }
+        )
+    }
+
+    #[test]
+    fn joint_puncts_are_expected() {
+        assert_function_macro_implementation_as_expected!(
+            crate::tests : parsed_tokens,
+            input: {
+                "a --b"
+            }
+
+            expected: {
+                a --b
+            }
+        )
+    }
+
+    #[test]
+    #[should_panic(expected = "first difference at token 1: found '-', expected '- (joint)'")]
+    fn separate_puncts_do_not_match_joint_ones() {
+        assert_function_macro_implementation_as_expected!(
+            crate::tests : parsed_tokens,
+            input: {
+                "a - -b"
+            }
+
+            expected: {
+                a --b
+            }
+        )
+    }
+
+    #[test]
+    fn function_macro_input_parse_error() {
+        assert_function_macro_implementation_as_expected!(
+            crate::tests : answer_fn,
+            input: {
+                42
+            }
+
+            expected: {
+                compile_error! { "expected identifier" }
+            }
+        )
+    }
+
+    #[test]
+    fn fallible_ok() {
+        assert_attribute_implementation_as_expected!(
+            crate::tests : only_structs,
+            item: {
+                #[only_structs]
+                struct S;
+            }
+
+            expected: {
+                struct S;
+            }
+        )
+    }
+
+    #[test]
+    fn fallible_error() {
+        assert_attribute_implementation_as_expected!(
+            crate::tests : only_structs,
+            item: {
+                #[only_structs]
+                enum E {}
+            }
+
+            expected: {
+                compile_error! { "only structs are supported" }
+                enum E {}
+            }
+        )
+    }
+
+    #[test]
+    fn typed_attribute_args() {
+        assert_attribute_implementation_as_expected!(
+            crate::tests : configurable_answer,
+            item: {
+                #[configurable_answer(value = 42)]
+                struct S;
+            }
+
+            expected: {
+                struct S;
+
+                impl S {
+                    pub fn get_answer() -> usize { 42 }
+                }
+            }
+        )
+    }
+
+    #[test]
+    fn typed_attribute_args_parse_error() {
+        assert_attribute_implementation_as_expected!(
+            crate::tests : configurable_answer,
+            item: {
+                #[configurable_answer(value = "42")]
+                struct S;
+            }
+
+            expected: {
+                compile_error! { "expected integer literal" }
+            }
+        )
+    }
+
+    #[test]
+    fn typed_item() {
+        assert_attribute_implementation_as_expected!(
+            crate::tests : typed_answer,
+            item: {
+                #[typed_answer]
+                struct S;
+            }
+
+            expected: {
+                struct S;
+
+                impl S {
+                    pub fn get_answer() -> usize { 42 }
+                }
+            }
+        )
+    }
+
+    #[test]
+    fn typed_item_of_wrong_kind() {
+        assert_attribute_implementation_as_expected!(
+            crate::tests : typed_answer,
+            item: {
+                #[typed_answer]
+                enum E {}
+            }
+
+            expected: {
+                compile_error! { "this attribute can only be applied to structs" }
+                enum E {}
+            }
+        )
+    }
+
+    #[test]
+    fn nested_method() {
+        assert_attribute_implementation_as_expected!(
+            crate::tests : answer_method,
+            item: {
+                impl S {
+                    pub fn new() -> Self { S }
+
+                    #[answer_method]
+                    pub fn get_answer() -> usize { 0 }
+                }
+            }
+
+            expected: {
+                pub fn get_answer() -> usize { 42 }
+            }
+        )
+    }
+
+    #[test]
+    fn nested_field() {
+        assert_attribute_implementation_as_expected!(
+            crate::tests : bar,
+            item: {
+                struct S {
+                    #[bar]
+                    foo: usize,
+                }
+            }
+
+            expected: {
+                foo: usize
+            }
+        )
+    }
+
+    #[test]
+    fn alias_behind_cfg_attr() {
+        assert_attribute_implementation_as_expected!(
+            crate::tests : configurable_answer,
+            aliases: [my_macros::configurable_answer],
+            cfg: [test],
+            item: {
+                #[cfg_attr(test, my_macros::configurable_answer(value = 42))]
+                #[cfg_attr(not(test), derive(Debug))]
+                struct S;
+            }
+
+            expected: {
+                struct S;
+
+                impl S {
+                    pub fn get_answer() -> usize { 42 }
+                }
+            }
+        )
+    }
+
+    #[test]
+    #[should_panic(expected = "Could not find the attribute 'bar'")]
+    fn attribute_with_other_path_is_ignored() {
+        assert_attribute_implementation_as_expected!(
+            crate::tests : bar,
+            item: {
+                #[other::bar]
+                struct S;
+            }
+
+            expected: {
+                struct S;
+            }
+        )
+    }
+
+    #[test]
+    fn raw_attribute_args() {
+        assert_attribute_implementation_as_expected!(
+            crate::tests : bounds,
+            item: {
+                #[bounds(T: Clone + 'static)]
+                struct S;
+            }
+
+            expected: {
+                struct S;
+
+                impl<T> S where T: Clone + 'static {}
+            }
+        )
+    }
+
+    #[test]
+    fn name_value_attribute_args() {
+        assert_attribute_implementation_as_expected!(
+            crate::tests : bar,
+            item: {
+                #[bar = "x"]
+                struct S;
+            }
+
+            expected: {
+                compile_error! { "key-value macro attributes are not supported" }
+            }
+        )
+    }
+
+    #[test]
+    fn expansion_compiles() {
+        assert_expansion_compiles!(
+            crate::tests : answer_method,
+            prelude: {
+                pub struct S;
+            }
+            item: {
+                impl S {
+                    #[answer_method]
+                    pub fn get_answer() -> usize { 0 }
+                }
+            }
+        )
+    }
+
+    #[test]
+    fn expansion_runs() {
+        assert_expansion_runs!(
+            crate::tests : configurable_answer,
+            item: {
+                #[configurable_answer(value = 42)]
+                struct S;
+            }
+            test: {
+                assert_eq!(S::get_answer(), 42);
+            }
+        )
+    }
+
+    #[test]
+    fn properties() {
+        assert_attribute_properties!(crate::tests : only_structs);
+        assert_attribute_properties!(
+            crate::tests : configurable_answer,
+            args: { value = 42 },
+            kinds: [Struct, TupleStruct, UnitStruct],
+            cases: 100,
+        )
+    }
+
+    #[test]
+    #[should_panic(expected = "Property violated: the implementation must not panic")]
+    fn properties_violated() {
+        assert_attribute_properties!(crate::tests : configurable_answer, args: { value = 42 }, kinds: [Enum])
+    }
+
+    #[test]
+    fn replay_corpus() {
+        let temp_dir = TempDir::new("replay_corpus");
+        let dir = temp_dir.path().join("configurable_answer");
+        let output = |value: usize| {
+            let value = proc_macro2::Literal::usize_unsuffixed(value);
+            quote! {
+                struct S;
+
+                impl S {
+                    pub fn get_answer() -> usize { #value }
+                }
+            }
+        };
+
+        crate::write_case(&dir, &quote! { value = 42 }, &quote! { struct S; }, &output(42)).unwrap();
+        crate::replay_corpus(&dir, configurable_answer::implementation);
+
+        crate::write_case(&dir, &quote! { value = 41 }, &quote! { struct S; }, &output(42)).unwrap();
+        let report = *std::panic::catch_unwind(|| crate::replay_corpus(&dir, configurable_answer::implementation))
+            .unwrap_err()
+            .downcast::<String>()
+            .unwrap();
+        assert!(report.starts_with("1 of 2 recorded cases differ from the recorded output"), "{}", report);
+    }
+
+    #[test]
+    fn generated_attribute_records_invocations() {
+        let workspace = TempDir::new("generated_attribute_records_invocations");
+        let corpus = workspace.path().join("corpus");
+        let files = [
+            ("Cargo.toml", "[workspace]\nmembers = [\"answer\", \"user\"]\n".to_string()),
+            ("answer/Cargo.toml", format!(
+                "[package]\nname = \"answer\"\nversion = \"0.0.0\"\nedition = \"2021\"\n\n[lib]\nproc-macro = true\n\n\
+                [dependencies]\nmacro-test-runtime = {{ path = {:?} }}\n",
+                std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("runtime")
+            )),
+            ("answer/src/lib.rs", "\
+                use macro_test_runtime::proc_macro_attribute2;\n\
+                use macro_test_runtime::proc_macro2::TokenStream as TokenStream2;\n\
+                use macro_test_runtime::quote::quote;\n\
+                use macro_test_runtime::syn::{AttributeArgs, ItemStruct};\n\
+                \n\
+                #[proc_macro_attribute2]\n\
+                pub fn typed_answer(_attr: AttributeArgs, item: ItemStruct) -> TokenStream2 {\n\
+                    let ident = &item.ident;\n\
+                    quote! { #item impl #ident { pub fn get_answer() -> usize { 42 } } }\n\
+                }\n".to_string()),
+            ("user/Cargo.toml", "[package]\nname = \"user\"\nversion = \"0.0.0\"\nedition = \"2021\"\n\n[dependencies]\nanswer = { path = \"../answer\" }\n".to_string()),
+            ("user/src/lib.rs", "#[answer::typed_answer]\npub struct S;\n\n#[answer::typed_answer]\npub struct T(u8);\n".to_string()),
+        ];
+        for (file, content) in files {
+            let path = workspace.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+
+        // cargo resolves the lock file of the workspace from the local registry cache, like for any new crate
+        let output = std::process::Command::new(std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into()))
+            .args(["check", "--offline", "--quiet", "--color", "never"])
+            .current_dir(workspace.path())
+            .env("CARGO_TARGET_DIR", workspace.path().join("target"))
+            .env(crate::RECORD_VARIABLE, &corpus)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+        let dir = corpus.join("typed_answer");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        crate::replay_corpus(&dir, typed_answer::implementation);
+    }
+
+    #[test]
+    fn implementations_equivalent() {
+        let test = PropertyTest::new(parse_quote!(configurable_answer))
+            .attribute_args(quote! { value = 42 })
+            .kinds(&[ItemKind::Struct, ItemKind::TupleStruct, ItemKind::UnitStruct])
+            .cases(50);
+
+        crate::assert_implementations_equivalent(
+            configurable_answer::implementation,
+            typed_configurable_answer::implementation,
+            Inputs::generated(&test)
+        );
+    }
+
+    #[test]
+    fn implementations_not_equivalent() {
+        let items = [parse_quote! { #[configurable_answer(value = 42)] struct S; }, parse_quote! { #[configurable_answer(value = 42)] enum E {} }];
+        let inputs = Inputs::items(&AttributeMatcher::new(parse_quote!(configurable_answer)), items);
+
+        let report = *std::panic::catch_unwind(|| crate::assert_implementations_equivalent(
+            configurable_answer::implementation,
+            typed_configurable_answer::implementation,
+            inputs
+        ))
+            .unwrap_err()
+            .downcast::<String>()
+            .unwrap();
+        assert!(report.starts_with("1 of 2 inputs expand differently\n\nItem 1\n"), "{}", report);
+        assert!(report.contains("The old implementation panicked"), "{}", report);
+    }
+
+    #[test]
+    fn recorded_corpus_is_replayed() {
+        let temp_dir = TempDir::new("recorded_corpus_is_replayed");
+        let dir = temp_dir.path().join("configurable_answer");
+        let items = [parse_quote! { #[configurable_answer(value = 42)] struct S; }, parse_quote! { #[configurable_answer(value = 7)] struct T(u8); }];
+
+        crate::record_corpus(&dir, configurable_answer::implementation, Inputs::items(&AttributeMatcher::new(parse_quote!(configurable_answer)), items));
+        crate::replay_corpus(&dir, typed_configurable_answer::implementation);
+        crate::assert_implementations_equivalent(
+            configurable_answer::implementation,
+            typed_configurable_answer::implementation,
+            Inputs::corpus(&dir)
+        );
+    }
+
+    #[test]
+    fn minimized_failure() {
+        let minimized = minimize_attribute_failure!(
+            crate::tests : configurable_answer,
+            item: {
+                /// The answer.
+                #[derive(Debug)]
+                #[configurable_answer(value = 42)]
+                pub enum E<T: Clone> where T: Default {
+                    A(T),
+                    B { value: u8 },
+                }
+            }
+            failure: panic
+        );
+
+        let item = &minimized.item;
+        assert_eq!(quote! { #item }.to_string(), quote! { #[configurable_answer(value = 42)] enum E {} }.to_string());
+    }
+
+    #[test]
+    fn renamed_module() {
+        assert_attribute_implementation_as_expected!(
+            crate::tests : answer,
+            module: renamed_answer_module,
+            item: {
+                #[answer]
+                struct S;
+            }
+
+            expected: {
+                struct S;
+            }
+        )
+    }
+
+    #[test]
+    fn snapshot() {
+        assert_attribute_implementation_as_expected!(
+            crate::tests : configurable_answer,
+            item: {
+                #[configurable_answer(value = 42)]
+                struct S;
+            }
+
+            snapshot
+        )
+    }
+
+    #[test]
+    fn expected_error() {
+        assert_attribute_implementation_as_expected!(
+            crate::tests : checked_fields,
+            item: {
+                #[checked_fields]
+                struct S {
+                    good: usize,
+                    bad: usize,
+                }
+            }
+
+            expected_error: "must not be named", span: bad
+        )
+    }
+
+    #[test]
+    fn expected_error_regex() {
+        assert_attribute_implementation_as_expected!(
+            crate::tests : typed_answer,
+            item: {
+                #[typed_answer]
+                enum E {}
+            }
+
+            expected_error: regex "^this attribute can only be applied to \\w+$", span: E
+        )
+    }
+
+    #[test]
+    #[should_panic(expected = "No error containing 'must not be named' covers the token 'good'")]
+    fn expected_error_at_wrong_token() {
+        assert_attribute_implementation_as_expected!(
+            crate::tests : checked_fields,
+            item: {
+                #[checked_fields]
+                struct S {
+                    good: usize,
+                    bad: usize,
+                }
+            }
+
+            expected_error: "must not be named", span: good
+        )
+    }
+
+    #[test]
+    fn placeholders() {
+        assert_attribute_implementation_as_expected!(
+            crate::tests : private_answer,
+            item: {
+                #[private_answer]
+                struct Foo(u8);
+            }
+
+            expected: {
+                struct $_ $..;
+
+                struct $private:ident;
+
+                impl Foo {
+                    fn private() -> $private:ident { $private:ident }
+                }
+            },
+            bindings: |bindings| assert_eq!(bindings["private"], "__Foo_private_afdd")
+        )
+    }
+
+    #[test]
+    #[should_panic(expected = "first difference at token 8.5: found '__Foo_private_afdd', expected 'Foo'")]
+    fn placeholder_bound_to_other_ident() {
+        assert_attribute_implementation_as_expected!(
+            crate::tests : private_answer,
+            item: {
+                #[private_answer]
+                struct Foo;
+            }
+
+            expected: {
+                struct $name:ident;
+
+                struct $private:ident;
+
+                impl $name:ident {
+                    fn private() -> $name:ident { $private:ident }
+                }
+            }
+        )
+    }
+
+    #[test]
+    fn try_compare_returns_mismatch() {
+        let failure = crate::try_compare_implementations(
+            configurable_answer::implementation,
+            crate::AttributeMatcher::new(syn::parse_quote! { configurable_answer }),
+            syn::parse_quote! {
+                #[configurable_answer(value = 41)]
+                struct S;
+            },
+            quote! {
+                struct S;
+
+                impl S {
+                    pub fn get_answer() -> usize { 42 }
+                }
+            },
+        ).unwrap_err();
+        let mismatch = match failure {
+            crate::ExpansionFailure::Mismatch(mismatch) => mismatch,
+            failure => panic!("unexpected failure: {}", failure)
+        };
+
+        assert_eq!(mismatch.mismatch.path, vec![5, 7, 0]);
+        assert_eq!(mismatch.attribute_args.to_string(), "value = 41");
+        let item = &mismatch.item;
+        assert_eq!(quote! { #item }.to_string(), "struct S ;");
+        assert!(mismatch.to_string().starts_with("The implementation does not match the expectation"));
+    }
+
+    #[test]
+    fn try_compare_returns_panic() {
+        let failure = crate::try_compare_implementations(
+            configurable_answer::implementation,
+            crate::AttributeMatcher::new(syn::parse_quote! { configurable_answer }),
+            syn::parse_quote! {
+                #[configurable_answer(value = 42)]
+                enum E {}
+            },
+            quote! {},
+        ).unwrap_err();
+
+        match failure {
+            crate::ExpansionFailure::Panic(panic) => {
+                assert!(panic.message.contains("expected `struct`"), "{}", panic.message);
+                assert_eq!(panic.attribute_args.to_string(), "value = 42");
+                assert!(panic.to_string().starts_with("The implementation panicked: "));
+            }
+            failure => panic!("unexpected failure: {}", failure)
+        }
+    }
+
+    #[test]
+    fn try_compare_returns_missing_attribute() {
+        let failure = crate::try_compare_implementations(
+            configurable_answer::implementation,
+            crate::AttributeMatcher::new(syn::parse_quote! { configurable_answer }),
+            syn::parse_quote! {
+                #[other(value = 42)]
+                struct S;
+            },
+            quote! {},
+        ).unwrap_err();
+
+        match failure {
+            crate::ExpansionFailure::AttributeNotFound(message) => {
+                assert!(message.starts_with("Could not find the attribute 'configurable_answer' on the item"), "{}", message)
+            }
+            failure => panic!("unexpected failure: {}", failure)
+        }
+    }
+
+    #[test]
+    fn try_compare_returns_invalid_item() {
+        let failure = crate::try_compare_implementations(
+            configurable_answer::implementation,
+            crate::AttributeMatcher::new(syn::parse_quote! { configurable_answer }).cfgs([syn::parse_quote! { test }]),
+            syn::parse_quote! {
+                #[cfg_attr(= test, configurable_answer(value = 42))]
+                struct S;
+            },
+            quote! {},
+        ).unwrap_err();
+
+        match failure {
+            crate::ExpansionFailure::InvalidItem(message) => {
+                assert!(message.starts_with("Could not parse the predicate of 'cfg_attr("), "{}", message)
+            }
+            failure => panic!("unexpected failure: {}", failure)
+        }
+    }
+
+    #[test]
+    fn attribute_is_given_by_ident() {
+        crate::compare_implementations(
+            configurable_answer::implementation,
+            Ident::new("configurable_answer", proc_macro2::Span::call_site()),
+            parse_quote! {
+                #[configurable_answer(value = 42)]
+                struct S;
+            },
+            quote! {
+                struct S;
+
+                impl S {
+                    pub fn get_answer() -> usize { 42 }
+                }
+            },
+        );
+    }
+
+    #[test]
+    fn cases_are_read_back() {
+        let temp_dir = TempDir::new("cases_are_read_back");
+        let dir = temp_dir.path();
+
+        let first = crate::write_case(dir, &quote! { value = 42 }, &quote! { struct S; }, &quote! { struct S; impl S {} }).unwrap();
+        let again = crate::write_case(dir, &quote! { value = 42 }, &quote! { struct S; }, &quote! { struct S; }).unwrap();
+        crate::write_case(dir, &quote! {}, &quote! { enum E {} }, &quote! { enum E {} }).unwrap();
+
+        let cases = crate::read_cases(dir);
+        assert_eq!(first, again);
+        assert_eq!(cases.len(), 2);
+        let case = cases.iter().find(|case| case.dir == first).unwrap();
+        assert_eq!(case.attribute_args.to_string(), "value = 42");
+        assert_eq!(case.item.to_string(), "struct S ;");
+        assert_eq!(case.output.to_string(), "struct S ;");
+    }
+}
@@ -0,0 +1,258 @@
+use proc_macro2::{Delimiter, Group, Spacing, TokenStream, TokenTree};
+use quote::ToTokens;
+use syn::{Attribute, Ident, Item, Meta, MetaList, NestedMeta, Path};
+
+/// Decides which attribute of a test item is the tested one. An attribute matches if its whole path is
+/// one of the paths of the matcher, so '#[other::answer]' doesn't match 'answer' unless it was added as
+/// an alias. A leading '::' is ignored.
+///
+/// If cfgs are given, 'cfg_attr' attributes are expanded like rustc does it before the attribute is
+/// searched: '#[cfg_attr(test, answer(42))]' becomes '#[answer(42)]' if 'test' is enabled and is removed
+/// otherwise.
+#[derive(Clone)]
+pub struct AttributeMatcher {
+    paths: Vec<Path>,
+    cfgs: Option<Vec<Meta>>,
+}
+
+impl AttributeMatcher {
+    pub fn new(path: Path) -> Self {
+        AttributeMatcher { paths: vec![path], cfgs: None }
+    }
+
+    /// Adds another path the attribute may be written with, e.g. 'my_macros::answer'.
+    pub fn alias(mut self, path: Path) -> Self {
+        self.paths.push(path);
+        self
+    }
+
+    /// Expands 'cfg_attr' attributes with the given cfgs enabled, like 'test' or 'feature = "answer"'. All
+    /// other cfgs are disabled.
+    pub fn cfgs<C: IntoIterator<Item = Meta>>(mut self, cfgs: C) -> Self {
+        self.cfgs = Some(cfgs.into_iter().collect());
+        self
+    }
+
+    pub fn matches(&self, attribute: &Attribute) -> bool {
+        self.paths.iter().any(|path| paths_equal(path, &attribute.path))
+    }
+
+    /// Expands the 'cfg_attr' attributes of the item and all nodes in it, if cfgs were given. Returns an
+    /// error if a 'cfg_attr' is malformed or the item can't be parsed after the expansion.
+    pub fn expand_cfg_attrs(&self, item: &mut Item) -> Result<(), String> {
+        if let Some(cfgs) = &self.cfgs {
+            *item = syn::parse2(expand_cfg_attrs(item.to_token_stream(), cfgs)?)
+                .map_err(|e| format!("Could not parse the item after expanding cfg_attr: {}", e))?;
+        }
+        Ok(())
+    }
+
+    /// Describes the matched paths for error messages, e.g. "'answer' or 'my_macros::answer'".
+    pub fn describe(&self) -> String {
+        self.paths
+            .iter()
+            .map(|path| format!("'{}'", path_to_string(path)))
+            .collect::<Vec<_>>()
+            .join(" or ")
+    }
+}
+
+impl From<Ident> for AttributeMatcher {
+    fn from(ident: Ident) -> Self {
+        AttributeMatcher::new(ident.into())
+    }
+}
+
+fn paths_equal(a: &Path, b: &Path) -> bool {
+    a.segments.len() == b.segments.len() && a.segments.iter().zip(&b.segments).all(|(a, b)| a.ident == b.ident)
+}
+
+fn path_to_string(path: &Path) -> String {
+    path.segments.iter().map(|segment| segment.ident.to_string()).collect::<Vec<_>>().join("::")
+}
+
+/// Replaces every '#[cfg_attr(predicate, attributes...)]' in the tokens, also in nested groups, with
+/// the attributes if the predicate holds and removes it otherwise. Inner attributes are handled the same way.
+fn expand_cfg_attrs(tokens: TokenStream, cfgs: &[Meta]) -> Result<TokenStream, String> {
+    let trees = tokens.into_iter().collect::<Vec<_>>();
+    let mut expanded = TokenStream::new();
+    let mut index = 0;
+
+    while index < trees.len() {
+        if let Some((prefix, attributes, length)) = cfg_attr_at(&trees[index..]) {
+            for attribute in expand_cfg_attr(attributes, cfgs)? {
+                let mut tokens = prefix.clone();
+                tokens.push(TokenTree::Group(attribute));
+                expanded.extend(expand_cfg_attrs(tokens.into_iter().collect(), cfgs)?);
+            }
+            index += length;
+            continue;
+        }
+
+        expanded.extend(Some(match &trees[index] {
+            TokenTree::Group(group) => {
+                let mut expanded_group = Group::new(group.delimiter(), expand_cfg_attrs(group.stream(), cfgs)?);
+                expanded_group.set_span(group.span());
+                TokenTree::Group(expanded_group)
+            }
+            tree => tree.clone()
+        }));
+        index += 1;
+    }
+
+    Ok(expanded)
+}
+
+/// Recognizes '#[cfg_attr(...)]' and '#![cfg_attr(...)]' at the start of the trees. Returns the '#' (and '!'),
+/// the arguments of cfg_attr and the number of trees of the attribute.
+fn cfg_attr_at(trees: &[TokenTree]) -> Option<(Vec<TokenTree>, TokenStream, usize)> {
+    let prefix_length = match (trees.first(), trees.get(1)) {
+        (Some(TokenTree::Punct(pound)), Some(TokenTree::Punct(bang))) if pound.as_char() == '#' && bang.as_char() == '!' => 2,
+        (Some(TokenTree::Punct(pound)), _) if pound.as_char() == '#' => 1,
+        _ => return None
+    };
+
+    let brackets = match trees.get(prefix_length) {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Bracket => group,
+        _ => return None
+    };
+
+    let mut content = brackets.stream().into_iter();
+    match (content.next(), content.next(), content.next()) {
+        (Some(TokenTree::Ident(ident)), Some(TokenTree::Group(args)), None)
+        if ident == "cfg_attr" && args.delimiter() == Delimiter::Parenthesis => {
+            Some((trees[..prefix_length].to_vec(), args.stream(), prefix_length + 1))
+        }
+        _ => None
+    }
+}
+
+/// Returns the bracketed attributes of 'cfg_attr' if its predicate holds.
+fn expand_cfg_attr(args: TokenStream, cfgs: &[Meta]) -> Result<Vec<Group>, String> {
+    let mut parts = split_at_commas(args.clone()).into_iter();
+    let predicate = parts.next()
+        .and_then(|predicate| syn::parse2::<Meta>(predicate).ok())
+        .ok_or_else(|| format!("Could not parse the predicate of 'cfg_attr({})'", args))?;
+
+    Ok(match cfg_enabled(&predicate, cfgs)? {
+        true => parts
+            .filter(|part| !part.is_empty())
+            .map(|part| Group::new(Delimiter::Bracket, part))
+            .collect(),
+        false => vec![]
+    })
+}
+
+fn split_at_commas(tokens: TokenStream) -> Vec<TokenStream> {
+    let mut parts = vec![TokenStream::new()];
+
+    for tree in tokens {
+        match &tree {
+            TokenTree::Punct(punct) if punct.as_char() == ',' && punct.spacing() == Spacing::Alone => {
+                parts.push(TokenStream::new())
+            }
+            _ => parts.last_mut().expect("there is always a part").extend(Some(tree))
+        }
+    }
+
+    parts
+}
+
+/// Evaluates a cfg predicate like 'all(test, not(feature = "answer"))'. Like rustc, predicates which aren't
+/// a cfg name or 'all', 'any' and 'not' with the right number of arguments are rejected.
+fn cfg_enabled(predicate: &Meta, cfgs: &[Meta]) -> Result<bool, String> {
+    let nested_enabled = |list: &MetaList| list.nested
+        .iter()
+        .map(|nested| match nested {
+            NestedMeta::Meta(meta) => cfg_enabled(meta, cfgs),
+            NestedMeta::Lit(lit) => Err(format!("Invalid cfg predicate '{}' in '{}'", lit.to_token_stream(), list.to_token_stream()))
+        })
+        .collect::<Result<Vec<_>, _>>();
+
+    match predicate {
+        Meta::List(list) if list.path.is_ident("all") => Ok(nested_enabled(list)?.into_iter().all(|enabled| enabled)),
+        Meta::List(list) if list.path.is_ident("any") => Ok(nested_enabled(list)?.into_iter().any(|enabled| enabled)),
+        Meta::List(list) if list.path.is_ident("not") => match nested_enabled(list)?.as_slice() {
+            [enabled] => Ok(!enabled),
+            _ => Err(format!("Invalid cfg predicate '{}', 'not' takes exactly one predicate", list.to_token_stream()))
+        },
+        Meta::List(list) => Err(format!("Invalid cfg predicate '{}'", list.to_token_stream())),
+        predicate => {
+            let predicate = predicate.to_token_stream().to_string();
+            Ok(cfgs.iter().any(|cfg| cfg.to_token_stream().to_string() == predicate))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::quote;
+    use syn::{parse_quote, Attribute, Item};
+    use syn::parse::Parser;
+    use crate::matcher::AttributeMatcher;
+
+    fn attribute(attribute: proc_macro2::TokenStream) -> Attribute {
+        Attribute::parse_outer.parse2(attribute).unwrap().remove(0)
+    }
+
+    #[test]
+    fn whole_path_has_to_match() {
+        let matcher = AttributeMatcher::new(parse_quote! { answer }).alias(parse_quote! { my_macros::answer });
+
+        assert!(matcher.matches(&attribute(quote! { #[answer] })));
+        assert!(matcher.matches(&attribute(quote! { #[::my_macros::answer(42)] })));
+        assert!(!matcher.matches(&attribute(quote! { #[other::answer] })));
+    }
+
+    #[test]
+    fn cfg_attr_is_expanded() {
+        let matcher = AttributeMatcher::new(parse_quote! { answer }).cfgs([parse_quote! { test }, parse_quote! { feature = "a" }]);
+        let mut item: Item = parse_quote! {
+            #[cfg_attr(all(test, not(feature = "b")), answer(42), inline)]
+            #[cfg_attr(feature = "b", derive(Debug))]
+            fn f() {
+                #![cfg_attr(any(feature = "b", feature = "a"), cfg_attr(test, allow(unused)))]
+            }
+        };
+        matcher.expand_cfg_attrs(&mut item).unwrap();
+
+        assert_eq!(
+            quote! { #item }.to_string(),
+            quote! {
+                #[answer(42)]
+                #[inline]
+                fn f() {
+                    #![allow(unused)]
+                }
+            }.to_string()
+        )
+    }
+
+    #[test]
+    fn malformed_cfg_attr_is_reported() {
+        let matcher = AttributeMatcher::new(parse_quote! { answer }).cfgs([parse_quote! { test }]);
+        let mut item: Item = parse_quote! {
+            #[cfg_attr(= test, answer)]
+            struct S;
+        };
+
+        assert_eq!(matcher.expand_cfg_attrs(&mut item).unwrap_err(), "Could not parse the predicate of 'cfg_attr(= test , answer)'");
+    }
+
+    #[test]
+    fn invalid_cfg_predicates_are_reported() {
+        let matcher = AttributeMatcher::new(parse_quote! { answer }).cfgs([parse_quote! { test }]);
+        let expand = |mut item: Item| matcher.expand_cfg_attrs(&mut item).unwrap_err();
+
+        assert_eq!(
+            expand(parse_quote! { #[cfg_attr(not(test, feature = "a"), answer)] struct S; }),
+            "Invalid cfg predicate 'not (test , feature = \"a\")', 'not' takes exactly one predicate"
+        );
+        assert_eq!(
+            expand(parse_quote! { #[cfg_attr(all(test, not()), answer)] struct S; }),
+            "Invalid cfg predicate 'not ()', 'not' takes exactly one predicate"
+        );
+        assert_eq!(expand(parse_quote! { #[cfg_attr(any("test"), answer)] struct S; }), "Invalid cfg predicate '\"test\"' in 'any (\"test\")'");
+        assert_eq!(expand(parse_quote! { #[cfg_attr(either(test), answer)] struct S; }), "Invalid cfg predicate 'either (test)'");
+    }
+}
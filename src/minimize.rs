@@ -0,0 +1,344 @@
+use proc_macro2::{Group, TokenStream, TokenTree};
+use quote::ToTokens;
+use syn::punctuated::Punctuated;
+use syn::visit_mut::{self, VisitMut};
+use syn::{Block, ExprMatch, Field, FieldsNamed, FieldsUnnamed, ForeignItem, Generics, ImplItem, Item, ItemEnum,
+          ItemForeignMod, ItemImpl, ItemMod, ItemTrait, Signature, Stmt, TraitItem, Variant};
+use crate::diff::pretty_print;
+use crate::extract::{foreign_item_attributes, impl_item_attributes, item_attributes, stmt_attributes, trait_item_attributes};
+
+/// The failure 'minimize_implementation_failure' keeps while it reduces the item.
+pub enum FailureCondition {
+    /// The implementation panics.
+    Panic,
+    /// The expansion doesn't parse as a Rust file.
+    UnparsableExpansion,
+    /// The expansion differs from the expected expansion of the item, which is computed for every reduced
+    /// item, e.g. with a reference implementation.
+    Mismatch(Box<dyn Fn(&Item) -> TokenStream>),
+}
+
+impl FailureCondition {
+    pub(crate) fn describe(&self) -> &'static str {
+        match self {
+            FailureCondition::Panic => "the implementation panics",
+            FailureCondition::UnparsableExpansion => "the expansion does not parse",
+            FailureCondition::Mismatch(_) => "the expansion differs from the expectation",
+        }
+    }
+}
+
+/// The smallest item found by 'minimize_implementation_failure' which still fails.
+pub struct MinimizedFailure {
+    /// The reduced item, including the tested attribute.
+    pub item: Item,
+    /// The expected expansion of the item, only known for FailureCondition::Mismatch.
+    pub expected: Option<TokenStream>,
+}
+
+impl MinimizedFailure {
+    /// Writes the failure as 'assert_attribute_implementation_as_expected!' call, ready to be pasted into a
+    /// test. If the expected expansion isn't known, 'expected' only contains a comment.
+    pub fn test_case(&self, base_path: &str, attribute: &str, module: Option<&str>) -> String {
+        let module = module.map(|module| format!(" module: {},", module)).unwrap_or_default();
+        let expected = match &self.expected {
+            Some(expected) => pretty_print(expected),
+            None => "// the expected expansion\n".to_string()
+        };
+
+        format!(
+            "assert_attribute_implementation_as_expected!(\n    {} : {},{}\n    item: {{\n{}    }}\n\n    expected: {{\n{}    }}\n)\n",
+            base_path,
+            attribute,
+            module,
+            indent(&pretty_print(&self.item.to_token_stream()), 8),
+            indent(&expected, 8)
+        )
+    }
+}
+
+fn indent(code: &str, width: usize) -> String {
+    code.lines()
+        .map(|line| match line.is_empty() {
+            true => "\n".to_string(),
+            false => format!("{:width$}{}\n", "", line, width = width)
+        })
+        .collect()
+}
+
+/// Reduces the item as long as it still fails. First, whole nodes are removed: attributes, fields, variants,
+/// generic parameters, where predicates, function parameters, statements, match arms and the items of impl
+/// blocks, traits and modules. Then token trees are removed from every token stream, in chunks of halving
+/// size like delta debugging does it. Candidates which are no valid items are skipped. Both steps are
+/// repeated until nothing can be removed anymore.
+pub fn reduce(mut item: Item, fails: impl Fn(&Item) -> bool) -> Item {
+    loop {
+        let before = item.to_token_stream().to_string();
+        item = remove_nodes(item, &fails);
+        item = remove_token_trees(item, &fails);
+
+        if item.to_token_stream().to_string() == before {
+            return item;
+        }
+    }
+}
+
+fn remove_nodes(mut item: Item, fails: &impl Fn(&Item) -> bool) -> Item {
+    let mut target = 0;
+
+    loop {
+        let mut candidate = item.clone();
+        let mut remover = NodeRemover { target, seen: 0 };
+        remover.visit_item_mut(&mut candidate);
+
+        if remover.seen <= target {
+            return item;
+        }
+        match fails(&candidate) {
+            // the next node moved to the removed position
+            true => item = candidate,
+            false => target += 1
+        }
+    }
+}
+
+/// Removes the removable node with the index 'target', counting in the order of the visit.
+struct NodeRemover {
+    target: usize,
+    seen: usize,
+}
+
+impl NodeRemover {
+    fn remove_from<T>(&mut self, nodes: &mut Vec<T>) {
+        let target = self.target;
+        let mut index = self.seen;
+        nodes.retain(|_| {
+            index += 1;
+            index - 1 != target
+        });
+        self.seen = index;
+    }
+
+    fn remove_from_punctuated<T, P: Default>(&mut self, nodes: &mut Punctuated<T, P>) {
+        let mut vec = std::mem::take(nodes).into_iter().collect::<Vec<_>>();
+        self.remove_from(&mut vec);
+        *nodes = vec.into_iter().collect();
+    }
+
+    fn remove_attributes<T>(&mut self, node: &mut T, attributes: fn(&mut T) -> Option<&mut Vec<syn::Attribute>>) {
+        if let Some(attributes) = attributes(node) {
+            self.remove_from(attributes)
+        }
+    }
+}
+
+impl VisitMut for NodeRemover {
+    fn visit_item_mut(&mut self, node: &mut Item) {
+        self.remove_attributes(node, item_attributes);
+        visit_mut::visit_item_mut(self, node)
+    }
+
+    fn visit_impl_item_mut(&mut self, node: &mut ImplItem) {
+        self.remove_attributes(node, impl_item_attributes);
+        visit_mut::visit_impl_item_mut(self, node)
+    }
+
+    fn visit_trait_item_mut(&mut self, node: &mut TraitItem) {
+        self.remove_attributes(node, trait_item_attributes);
+        visit_mut::visit_trait_item_mut(self, node)
+    }
+
+    fn visit_foreign_item_mut(&mut self, node: &mut ForeignItem) {
+        self.remove_attributes(node, foreign_item_attributes);
+        visit_mut::visit_foreign_item_mut(self, node)
+    }
+
+    fn visit_field_mut(&mut self, node: &mut Field) {
+        self.remove_from(&mut node.attrs);
+        visit_mut::visit_field_mut(self, node)
+    }
+
+    fn visit_variant_mut(&mut self, node: &mut Variant) {
+        self.remove_from(&mut node.attrs);
+        visit_mut::visit_variant_mut(self, node)
+    }
+
+    fn visit_stmt_mut(&mut self, node: &mut Stmt) {
+        self.remove_attributes(node, stmt_attributes);
+        visit_mut::visit_stmt_mut(self, node)
+    }
+
+    fn visit_fields_named_mut(&mut self, node: &mut FieldsNamed) {
+        self.remove_from_punctuated(&mut node.named);
+        visit_mut::visit_fields_named_mut(self, node)
+    }
+
+    fn visit_fields_unnamed_mut(&mut self, node: &mut FieldsUnnamed) {
+        self.remove_from_punctuated(&mut node.unnamed);
+        visit_mut::visit_fields_unnamed_mut(self, node)
+    }
+
+    fn visit_item_enum_mut(&mut self, node: &mut ItemEnum) {
+        self.remove_from_punctuated(&mut node.variants);
+        visit_mut::visit_item_enum_mut(self, node)
+    }
+
+    fn visit_generics_mut(&mut self, node: &mut Generics) {
+        self.remove_from_punctuated(&mut node.params);
+        if node.params.is_empty() {
+            node.lt_token = None;
+            node.gt_token = None;
+        }
+        if let Some(where_clause) = &mut node.where_clause {
+            self.remove_from_punctuated(&mut where_clause.predicates);
+            if where_clause.predicates.is_empty() {
+                node.where_clause = None;
+            }
+        }
+        visit_mut::visit_generics_mut(self, node)
+    }
+
+    fn visit_signature_mut(&mut self, node: &mut Signature) {
+        self.remove_from_punctuated(&mut node.inputs);
+        visit_mut::visit_signature_mut(self, node)
+    }
+
+    fn visit_block_mut(&mut self, node: &mut Block) {
+        self.remove_from(&mut node.stmts);
+        visit_mut::visit_block_mut(self, node)
+    }
+
+    fn visit_expr_match_mut(&mut self, node: &mut ExprMatch) {
+        self.remove_from(&mut node.arms);
+        visit_mut::visit_expr_match_mut(self, node)
+    }
+
+    fn visit_item_impl_mut(&mut self, node: &mut ItemImpl) {
+        self.remove_from(&mut node.items);
+        visit_mut::visit_item_impl_mut(self, node)
+    }
+
+    fn visit_item_trait_mut(&mut self, node: &mut ItemTrait) {
+        self.remove_from(&mut node.items);
+        visit_mut::visit_item_trait_mut(self, node)
+    }
+
+    fn visit_item_mod_mut(&mut self, node: &mut ItemMod) {
+        if let Some((_, items)) = &mut node.content {
+            self.remove_from(items);
+        }
+        visit_mut::visit_item_mod_mut(self, node)
+    }
+
+    fn visit_item_foreign_mod_mut(&mut self, node: &mut ItemForeignMod) {
+        self.remove_from(&mut node.items);
+        visit_mut::visit_item_foreign_mod_mut(self, node)
+    }
+}
+
+/// Removes chunks of token trees from every stream of the item, the outermost stream first and then the
+/// streams of its groups in the order they appear.
+fn remove_token_trees(mut item: Item, fails: &impl Fn(&Item) -> bool) -> Item {
+    let mut tokens = item.to_token_stream();
+    let mut stream = 0;
+
+    while stream < count_streams(&tokens) {
+        let mut chunk = stream_length(&tokens, stream);
+
+        while chunk > 0 {
+            let mut start = 0;
+
+            while start < stream_length(&tokens, stream) {
+                let candidate = edit_stream(&tokens, stream, &mut 0, &mut |trees| {
+                    trees.drain(start..(start + chunk).min(trees.len()));
+                });
+
+                match syn::parse2::<Item>(candidate.clone()).ok().filter(|candidate| fails(candidate)) {
+                    // the following trees moved to the start of the removed chunk
+                    Some(reduced) => {
+                        item = reduced;
+                        tokens = candidate;
+                    }
+                    None => start += chunk
+                }
+            }
+
+            chunk /= 2;
+        }
+
+        stream += 1;
+    }
+
+    item
+}
+
+/// The number of token streams, i.e. the stream itself and all nested groups.
+fn count_streams(tokens: &TokenStream) -> usize {
+    1 + tokens.clone()
+        .into_iter()
+        .map(|tree| match tree {
+            TokenTree::Group(group) => count_streams(&group.stream()),
+            _ => 0
+        })
+        .sum::<usize>()
+}
+
+fn stream_length(tokens: &TokenStream, stream: usize) -> usize {
+    let mut length = 0;
+    edit_stream(tokens, stream, &mut 0, &mut |trees| length = trees.len());
+    length
+}
+
+/// Applies the edit to the trees of the stream with the given index, counting like 'count_streams'.
+fn edit_stream(tokens: &TokenStream, stream: usize, index: &mut usize, edit: &mut dyn FnMut(&mut Vec<TokenTree>)) -> TokenStream {
+    let mut trees = tokens.clone().into_iter().collect::<Vec<_>>();
+
+    if *index == stream {
+        edit(&mut trees);
+    }
+    *index += 1;
+
+    trees.into_iter()
+        .map(|tree| match tree {
+            TokenTree::Group(group) => {
+                let mut edited = Group::new(group.delimiter(), edit_stream(&group.stream(), stream, index, edit));
+                edited.set_span(group.span());
+                TokenTree::Group(edited)
+            }
+            tree => tree
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::{quote, ToTokens};
+    use syn::{parse_quote, Item};
+    use crate::minimize::{reduce, MinimizedFailure};
+
+    #[test]
+    fn item_is_reduced_to_the_failing_part() {
+        let item: Item = parse_quote! {
+            #[derive(Debug)]
+            pub struct S<T: Clone> where T: Default {
+                #[serde(skip)]
+                first: Vec<T>,
+                bad: Option<u8>,
+                last: u8,
+            }
+        };
+        let mentions_option = |item: &Item| item.to_token_stream().to_string().contains("Option");
+
+        assert_eq!(reduce(item, mentions_option).to_token_stream().to_string(), quote! { struct S { bad: Option } }.to_string());
+    }
+
+    #[test]
+    fn test_case_is_ready_to_paste() {
+        let failure = MinimizedFailure { item: parse_quote! { #[answer] struct S; }, expected: None };
+
+        assert_eq!(
+            failure.test_case("crate::tests", "answer", Some("imp")),
+            "assert_attribute_implementation_as_expected!(\n    crate::tests : answer, module: imp,\n    item: {\n        #[answer]\n        struct S;\n    }\n\n    expected: {\n        // the expected expansion\n    }\n)\n"
+        )
+    }
+}
@@ -0,0 +1,107 @@
+use std::any::Any;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::cell::Cell;
+use std::sync::Once;
+use std::fmt::{Debug, Display, Formatter};
+use proc_macro2::TokenStream;
+use quote::ToTokens;
+use syn::Item;
+use macro_test_runtime::panic_message;
+use crate::compare::DebugItem;
+use crate::diff::pretty_print;
+
+/// An attribute implementation which panicked instead of returning an expansion, returned by
+/// 'try_compare_implementations'. Its Display implementation is the report the other test functions panic with.
+pub struct ImplementationPanic {
+    /// The message of the panic, if its payload is a string.
+    pub message: String,
+    /// The payload of the panic, which can be passed to std::panic::resume_unwind.
+    pub payload: Box<dyn Any + Send>,
+    /// The arguments of the attribute, i.e. the tokens between its delimiters.
+    pub attribute_args: TokenStream,
+    /// The item given to the test without the attribute.
+    pub item: Item,
+}
+
+impl ImplementationPanic {
+    pub(crate) fn new(payload: Box<dyn Any + Send>, attribute_args: TokenStream, item: Item) -> Self {
+        ImplementationPanic { message: panic_message(payload.as_ref()), payload, attribute_args, item }
+    }
+}
+
+impl Display for ImplementationPanic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "The implementation panicked: {}", self.message)?;
+        writeln!(f, "Attribute arguments: {}", self.attribute_args)?;
+        write!(f, "Item:\n{}", pretty_print(&self.item.to_token_stream()))
+    }
+}
+
+impl Debug for ImplementationPanic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ImplementationPanic")
+            .field("message", &self.message)
+            .field("attribute_args", &self.attribute_args)
+            .field("item", &DebugItem(&self.item))
+            .finish()
+    }
+}
+
+impl std::error::Error for ImplementationPanic {}
+
+thread_local! {
+    /// Set while 'without_panic_output' runs on the thread.
+    static SUPPRESSED: Cell<bool> = const { Cell::new(false) };
+}
+
+static FILTERING_HOOK: Once = Once::new();
+
+/// Runs the function without printing the panics of the current thread, e.g. while trying many inputs which
+/// make the implementation panic. The panic hook is only replaced once, by a hook which skips the panics of
+/// suppressed threads and passes all others to the previous hook, so calls in parallel tests don't race.
+pub(crate) fn without_panic_output<T>(f: impl FnOnce() -> T) -> T {
+    catch_panic_silently(f).unwrap_or_else(|payload| resume_unwind(payload))
+}
+
+/// Like 'catch_unwind', but the caught panic isn't printed, see 'without_panic_output'.
+pub(crate) fn catch_panic_silently<T>(f: impl FnOnce() -> T) -> std::thread::Result<T> {
+    FILTERING_HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if !SUPPRESSED.with(Cell::get) {
+                previous(info)
+            }
+        }));
+    });
+
+    let suppressed = SUPPRESSED.with(|flag| flag.replace(true));
+    let result = catch_unwind(AssertUnwindSafe(f));
+    SUPPRESSED.with(|flag| flag.set(suppressed));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::panic::catch_unwind;
+    use crate::panic::{without_panic_output, SUPPRESSED};
+
+    #[test]
+    fn suppression_ends_with_the_call() {
+        let threads = (0..4)
+            .map(|_| std::thread::spawn(|| for _ in 0..5 {
+                let payload = without_panic_output(|| {
+                    without_panic_output(|| ());
+                    assert!(SUPPRESSED.with(Cell::get));
+                    catch_unwind(|| panic!("suppressed")).unwrap_err()
+                });
+                assert!(!SUPPRESSED.with(Cell::get));
+                assert_eq!(*payload.downcast::<&str>().unwrap(), "suppressed");
+            }))
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::ops::Index;
+use proc_macro2::{Delimiter, Group, Ident, Punct, Spacing, TokenStream, TokenTree};
+use crate::compare::{flatten, trees_equal, TokenMismatch};
+
+/// The identifiers bound by the '$name:ident' placeholders of an expectation, returned if the expansion
+/// matches it. Index it with the name of the placeholder, e.g. 'bindings["helper"]'.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Bindings {
+    idents: BTreeMap<String, Ident>,
+}
+
+impl Bindings {
+    pub fn get(&self, name: &str) -> Option<&Ident> {
+        self.idents.get(name)
+    }
+
+    /// The bindings sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Ident)> {
+        self.idents.iter().map(|(name, ident)| (name.as_str(), ident))
+    }
+
+    pub fn len(&self) -> usize {
+        self.idents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.idents.is_empty()
+    }
+
+    /// Binds the ident to the name, unless the name is already bound to another ident, which is returned then.
+    fn bind(&mut self, name: &Ident, ident: &Ident) -> Result<(), Ident> {
+        match self.idents.get(&name.to_string()) {
+            Some(bound) if bound != ident => Err(bound.clone()),
+            Some(_) => Ok(()),
+            None => {
+                self.idents.insert(name.to_string(), ident.clone());
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Index<&str> for Bindings {
+    type Output = Ident;
+
+    fn index(&self, name: &str) -> &Ident {
+        self.get(name).unwrap_or_else(|| panic!("The expectation contains no placeholder '${}:ident'", name))
+    }
+}
+
+impl Display for Bindings {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let bindings = self.iter().map(|(name, ident)| format!("${} = {}", name, ident)).collect::<Vec<_>>();
+        f.write_str(&bindings.join(", "))
+    }
+}
+
+/// A token tree of an expectation, or one of its placeholders.
+enum Pattern {
+    /// A token tree which has to be equal, together with the tree following it for the spacing of puncts.
+    Tree(TokenTree, Option<TokenTree>),
+    Group(TokenTree, Delimiter, Vec<Pattern>),
+    /// '$_', stores the '_' to report it if the expansion ended early.
+    AnyTree(TokenTree),
+    /// '$..'
+    AnySequence,
+    /// '$name:ident', stores the name and the 'ident' token.
+    Ident(Ident, TokenTree),
+}
+
+/// Matches an expansion against an expectation which may contain placeholders: '$_' matches any token tree,
+/// '$..' any (possibly empty) sequence of token trees in its stream and '$name:ident' any ident, which has to
+/// be the same ident for all placeholders of that name. '$$' matches a single '$', e.g. in a generated
+/// macro_rules. All other tokens are compared like in 'compare_token_streams'. Placeholders aren't needed in
+/// recorded outputs, so they are only supported here, where the expectation is written by hand.
+///
+/// If '$..' can match in several ways, the first match wins, and the reported mismatch is the one furthest
+/// into the expansion. The path of a mismatch points at the token of the expansion.
+pub fn match_token_streams(actual: &TokenStream, expected: &TokenStream) -> Result<Bindings, TokenMismatch> {
+    let mut bindings = Bindings::default();
+    match_streams(&flatten(actual), 0, &parse_patterns(expected), &mut bindings, &[])?;
+    Ok(bindings)
+}
+
+fn match_streams(actual: &[TokenTree], start: usize, patterns: &[Pattern], bindings: &mut Bindings, path: &[usize]) -> Result<(), TokenMismatch> {
+    let mismatch = |index: usize, expected: Option<&TokenTree>| TokenMismatch {
+        path: [path, &[index]].concat(),
+        actual: actual.get(index).cloned(),
+        expected: expected.cloned(),
+    };
+
+    for (pattern_index, pattern) in patterns.iter().enumerate() {
+        let index = start + pattern_index;
+
+        match pattern {
+            Pattern::AnySequence => return match_sequence(actual, index, &patterns[pattern_index + 1..], bindings, path),
+            Pattern::AnyTree(placeholder) => if index >= actual.len() {
+                return Err(mismatch(index, Some(placeholder)))
+            },
+            Pattern::Ident(name, kind) => match actual.get(index) {
+                Some(TokenTree::Ident(ident)) => bindings.bind(name, ident)
+                    .map_err(|bound| mismatch(index, Some(&TokenTree::Ident(bound))))?,
+                _ => return Err(mismatch(index, Some(kind)))
+            },
+            Pattern::Group(group, delimiter, inner) => match actual.get(index) {
+                Some(TokenTree::Group(a)) if a.delimiter() == *delimiter => {
+                    match_streams(&flatten(&a.stream()), 0, inner, bindings, &[path, &[index]].concat())?
+                }
+                _ => return Err(mismatch(index, Some(group)))
+            },
+            Pattern::Tree(tree, next) => match actual.get(index) {
+                Some(a) if trees_equal(a, actual.get(index + 1), tree, next.as_ref()) => {}
+                _ => return Err(mismatch(index, Some(tree)))
+            }
+        }
+    }
+
+    match start + patterns.len() < actual.len() {
+        true => Err(mismatch(start + patterns.len(), None)),
+        false => Ok(())
+    }
+}
+
+/// Lets '$..' match as few trees as possible. The bindings of failed attempts are discarded. If all attempts
+/// fail, the mismatch of the attempt which matched the most trees after the sequence is returned.
+fn match_sequence(actual: &[TokenTree], start: usize, rest: &[Pattern], bindings: &mut Bindings, path: &[usize]) -> Result<(), TokenMismatch> {
+    let mut best: Option<(Vec<usize>, TokenMismatch)> = None;
+
+    for skipped in start..=actual.len() {
+        let mut attempt = bindings.clone();
+        match match_streams(actual, skipped, rest, &mut attempt, path) {
+            Ok(()) => {
+                *bindings = attempt;
+                return Ok(());
+            }
+            Err(mismatch) => {
+                // the path of the mismatch relative to the end of the sequence
+                let mut progress = mismatch.path[path.len()..].to_vec();
+                progress[0] -= skipped;
+                if best.as_ref().map(|(best, _)| &progress >= best).unwrap_or(true) {
+                    best = Some((progress, mismatch))
+                }
+            }
+        }
+    }
+
+    // there is at least one attempt, as 'start' is at most the length of the stream
+    Err(best.unwrap().1)
+}
+
+/// Replaces the placeholders of the expectation by tokens, so it can still be pretty-printed in a diff:
+/// '$name:ident' by 'name', '$_' by '_' and '$..' by '..'.
+pub fn printable_expectation(expected: &TokenStream) -> TokenStream {
+    print_patterns(&parse_patterns(expected))
+}
+
+fn print_patterns(patterns: &[Pattern]) -> TokenStream {
+    patterns.iter()
+        .flat_map(|pattern| match pattern {
+            Pattern::Tree(tree, _) | Pattern::AnyTree(tree) => vec![tree.clone()],
+            Pattern::Group(group, delimiter, inner) => {
+                let mut printed = Group::new(*delimiter, print_patterns(inner));
+                printed.set_span(group.span());
+                vec![TokenTree::Group(printed)]
+            }
+            Pattern::AnySequence => vec![
+                TokenTree::Punct(Punct::new('.', Spacing::Joint)),
+                TokenTree::Punct(Punct::new('.', Spacing::Alone)),
+            ],
+            Pattern::Ident(name, _) => vec![TokenTree::Ident(name.clone())],
+        })
+        .collect()
+}
+
+fn parse_patterns(stream: &TokenStream) -> Vec<Pattern> {
+    let trees = flatten(stream);
+    let mut patterns = vec![];
+    let mut index = 0;
+
+    while index < trees.len() {
+        let (pattern, length) = match (&trees[index], trees.get(index + 1), trees.get(index + 2), trees.get(index + 3)) {
+            (TokenTree::Punct(dollar), Some(TokenTree::Punct(escaped)), _, _) if dollar.as_char() == '$' && escaped.as_char() == '$' => {
+                (Pattern::Tree(trees[index + 1].clone(), trees.get(index + 2).cloned()), 2)
+            }
+            (TokenTree::Punct(dollar), Some(TokenTree::Ident(underscore)), _, _) if dollar.as_char() == '$' && underscore == "_" => {
+                (Pattern::AnyTree(trees[index + 1].clone()), 2)
+            }
+            (TokenTree::Punct(dollar), Some(TokenTree::Punct(first)), Some(TokenTree::Punct(second)), _)
+                if dollar.as_char() == '$' && first.as_char() == '.' && first.spacing() == Spacing::Joint && second.as_char() == '.' => {
+                (Pattern::AnySequence, 3)
+            }
+            (TokenTree::Punct(dollar), Some(TokenTree::Ident(name)), Some(TokenTree::Punct(colon)), Some(TokenTree::Ident(kind)))
+                if dollar.as_char() == '$' && colon.as_char() == ':' && colon.spacing() == Spacing::Alone && kind == "ident" => {
+                (Pattern::Ident(name.clone(), trees[index + 3].clone()), 4)
+            }
+            (TokenTree::Group(group), _, _, _) => (Pattern::Group(trees[index].clone(), group.delimiter(), parse_patterns(&group.stream())), 1),
+            (tree, next, _, _) => (Pattern::Tree(tree.clone(), next.cloned()), 1)
+        };
+
+        patterns.push(pattern);
+        index += length;
+    }
+
+    patterns
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use proc_macro2::TokenStream;
+    use crate::pattern::{match_token_streams, printable_expectation};
+
+    fn stream(s: &str) -> TokenStream {
+        TokenStream::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn placeholders_match_generated_tokens() {
+        let actual = stream("struct __Foo_private_3a9f; impl Foo { fn get(&self) -> u8 { 42 } } impl __Foo_private_3a9f {}");
+        let expected = stream("struct $private:ident; impl Foo { fn $_(&self) -> u8 { $.. } } impl $private:ident {}");
+
+        let bindings = match_token_streams(&actual, &expected).unwrap();
+        assert_eq!(bindings["private"], "__Foo_private_3a9f");
+        assert_eq!(bindings.to_string(), "$private = __Foo_private_3a9f");
+    }
+
+    #[test]
+    fn binding_has_to_match_the_same_ident() {
+        let mismatch = match_token_streams(&stream("struct A; impl B {}"), &stream("struct $name:ident; impl $name:ident {}")).unwrap_err();
+
+        assert_eq!(mismatch.to_string(), "first difference at token 4: found 'B', expected 'A'")
+    }
+
+    #[test]
+    fn sequence_backtracks() {
+        assert!(match_token_streams(&stream("a b c a b d"), &stream("$.. a b d")).is_ok());
+        assert!(match_token_streams(&stream("f(a, b)"), &stream("f($..)")).is_ok());
+        assert!(match_token_streams(&stream("f()"), &stream("f($..)")).is_ok());
+
+        let mismatch = match_token_streams(&stream("a b c a b e"), &stream("$.. a b d")).unwrap_err();
+        assert_eq!(mismatch.to_string(), "first difference at token 5: found 'e', expected 'd'")
+    }
+
+    #[test]
+    fn escaped_dollar_is_compared() {
+        assert!(match_token_streams(&stream("macro_rules! m { ($x:ident) => {} }"), &stream("macro_rules! m { ($$x:ident) => {} }")).is_ok());
+        assert!(match_token_streams(&stream("macro_rules! m { ($x:ident) => {} }"), &stream("macro_rules! m { ($x:ident) => {} }")).is_err());
+    }
+
+    #[test]
+    fn placeholders_are_printable() {
+        let printed = printable_expectation(&stream("struct $name:ident; impl $_ { fn f() { $.. } } macro_rules! m { ($$x:ident) => {} }"));
+        assert_eq!(printed.to_string(), stream("struct name; impl _ { fn f() { .. } } macro_rules! m { ($x:ident) => {} }").to_string());
+    }
+}
@@ -0,0 +1,544 @@
+use proc_macro2::{TokenStream, TokenTree};
+use syn::{Ident, Item};
+use macro_test_runtime::panic_message;
+use crate::diff::pretty_print;
+use crate::matcher::AttributeMatcher;
+use crate::panic::catch_panic_silently;
+
+/// The kinds of items the generator creates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ItemKind {
+    /// A struct with named fields.
+    Struct,
+    TupleStruct,
+    UnitStruct,
+    Enum,
+    Fn,
+    Trait,
+}
+
+impl ItemKind {
+    pub const ALL: [ItemKind; 6] = [
+        ItemKind::Struct,
+        ItemKind::TupleStruct,
+        ItemKind::UnitStruct,
+        ItemKind::Enum,
+        ItemKind::Fn,
+        ItemKind::Trait,
+    ];
+}
+
+/// A generator of varied items: with and without generics, lifetimes, const parameters, where clauses,
+/// attributes, visibilities and raw identifiers. The same seed generates the same items.
+pub struct ItemGenerator {
+    rng: Rng,
+    kinds: Vec<ItemKind>,
+}
+
+impl ItemGenerator {
+    pub fn new(seed: u64) -> Self {
+        ItemGenerator { rng: Rng(seed), kinds: ItemKind::ALL.to_vec() }
+    }
+
+    /// Restricts the generated items to the given kinds.
+    pub fn kinds(mut self, kinds: &[ItemKind]) -> Self {
+        assert!(!kinds.is_empty(), "at least one kind of items has to be generated");
+        self.kinds = kinds.to_vec();
+        self
+    }
+
+    pub fn next_item(&mut self) -> Item {
+        self.next_spec().to_item(None)
+    }
+
+    fn next_spec(&mut self) -> ItemSpec {
+        ItemSpec::generate(&mut self.rng, &self.kinds)
+    }
+}
+
+/// Checks invariants of an attribute implementation for many generated items, see
+/// 'assert_attribute_properties'. For every item, the implementation must not panic, its expansion has to
+/// parse as syn::File and it has to contain the identifier of the item. Like in 'compare_implementations',
+/// errors are expanded to the compile error followed by the item, so returning errors is fine.
+///
+/// A failing item is shrunk to a minimal item which still fails: attributes, fields, variants, parameters,
+/// generics and where predicates are removed one after another while the failure persists.
+#[derive(Clone)]
+pub struct PropertyTest {
+    attribute: Ident,
+    attribute_args: TokenStream,
+    cases: usize,
+    seed: u64,
+    kinds: Vec<ItemKind>,
+}
+
+impl PropertyTest {
+    /// Creates a property test for the attribute with the given name, which is placed on every generated item.
+    pub fn new(attribute: Ident) -> Self {
+        PropertyTest {
+            attribute,
+            attribute_args: TokenStream::new(),
+            cases: 256,
+            seed: 0,
+            kinds: ItemKind::ALL.to_vec(),
+        }
+    }
+
+    /// The arguments of the tested attribute, like 'value = 42' for '#[answer(value = 42)]'.
+    pub fn attribute_args(mut self, attribute_args: TokenStream) -> Self {
+        self.attribute_args = attribute_args;
+        self
+    }
+
+    /// The number of generated items, 256 by default.
+    pub fn cases(mut self, cases: usize) -> Self {
+        self.cases = cases;
+        self
+    }
+
+    /// The seed of the generator, 0 by default. Different seeds generate different items.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Restricts the generated items to the given kinds, e.g. if the attribute only supports structs.
+    pub fn kinds(mut self, kinds: &[ItemKind]) -> Self {
+        assert!(!kinds.is_empty(), "at least one kind of items has to be generated");
+        self.kinds = kinds.to_vec();
+        self
+    }
+
+    /// Runs the test with the given function, which expands an item carrying the tested attribute. Panics of
+    /// the function are not printed while the items are checked and shrunk, they are reported in the failure.
+    pub fn run<F: Fn(Item) -> TokenStream>(&self, expand: F) -> Result<(), Box<PropertyFailure>> {
+        let mut generator = ItemGenerator::new(self.seed).kinds(&self.kinds);
+
+        for case in 0..self.cases {
+            let spec = generator.next_spec();
+
+            if let Err(violation) = self.check(&spec, &expand) {
+                let (shrunk, violation) = self.shrink(spec.clone(), violation, &expand);
+                return Err(Box::new(PropertyFailure {
+                    case,
+                    seed: self.seed,
+                    original: self.render(&spec),
+                    shrunk: self.render(&shrunk),
+                    violation,
+                }));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check<F: Fn(Item) -> TokenStream>(&self, spec: &ItemSpec, expand: &F) -> Result<(), Violation> {
+        let item = spec.to_item(Some(&self.tested_attribute()));
+        let ident = spec.ident();
+
+        let expansion = catch_panic_silently(|| expand(item)).map_err(|payload| Violation {
+            property: "the implementation must not panic",
+            detail: panic_message(payload.as_ref()),
+            expansion: None,
+        })?;
+
+        if let Err(error) = syn::parse2::<syn::File>(expansion.clone()) {
+            return Err(Violation {
+                property: "the expansion has to parse as syn::File",
+                detail: error.to_string(),
+                expansion: Some(expansion),
+            });
+        }
+
+        if !contains_ident(&expansion, &ident) {
+            return Err(Violation {
+                property: "the expansion has to contain the identifier of the item",
+                detail: format!("'{}' is missing", ident),
+                expansion: Some(expansion),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the item with smaller variants as long as they still violate a property.
+    fn shrink<F: Fn(Item) -> TokenStream>(&self, mut spec: ItemSpec, mut violation: Violation, expand: &F) -> (ItemSpec, Violation) {
+        // every step removes something, so this limit is only a safeguard
+        for _ in 0..1000 {
+            let smaller = spec.shrink_candidates()
+                .into_iter()
+                .find_map(|candidate| self.check(&candidate, expand).err().map(|v| (candidate, v)));
+
+            match smaller {
+                Some((candidate, candidate_violation)) => {
+                    spec = candidate;
+                    violation = candidate_violation;
+                }
+                None => break
+            }
+        }
+
+        (spec, violation)
+    }
+
+    /// The generated items, each carrying the tested attribute.
+    pub(crate) fn items(&self) -> Vec<Item> {
+        let mut generator = ItemGenerator::new(self.seed).kinds(&self.kinds);
+        (0..self.cases).map(|_| generator.next_spec().to_item(Some(&self.tested_attribute()))).collect()
+    }
+
+    pub(crate) fn matcher(&self) -> AttributeMatcher {
+        AttributeMatcher::from(self.attribute.clone())
+    }
+
+    fn tested_attribute(&self) -> String {
+        match self.attribute_args.is_empty() {
+            true => format!("#[{}]", self.attribute),
+            false => format!("#[{}({})]", self.attribute, self.attribute_args),
+        }
+    }
+
+    fn render(&self, spec: &ItemSpec) -> String {
+        pretty_print(&spec.to_tokens(Some(&self.tested_attribute())))
+    }
+}
+
+/// A violated property, together with the item that violates it.
+#[derive(Clone, Debug)]
+pub struct PropertyFailure {
+    /// The number of the generated item which failed first.
+    pub case: usize,
+    pub seed: u64,
+    /// The first failing item.
+    pub original: String,
+    /// The smallest failing item found by shrinking.
+    pub shrunk: String,
+    pub violation: Violation,
+}
+
+#[derive(Clone, Debug)]
+pub struct Violation {
+    pub property: &'static str,
+    pub detail: String,
+    /// The expansion of the item, None if the implementation panicked.
+    pub expansion: Option<TokenStream>,
+}
+
+impl std::fmt::Display for PropertyFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Property violated: {} ({})", self.violation.property, self.violation.detail)?;
+        writeln!(f, "Shrunk item:\n{}", self.shrunk)?;
+        if let Some(expansion) = &self.violation.expansion {
+            writeln!(f, "Expansion of the shrunk item:\n{}", pretty_print(expansion))?;
+        }
+        write!(f, "Generated item {} of seed {} before shrinking:\n{}", self.case, self.seed, self.original)
+    }
+}
+
+fn contains_ident(tokens: &TokenStream, ident: &Ident) -> bool {
+    tokens.clone().into_iter().any(|tree| match tree {
+        TokenTree::Ident(i) => i == *ident,
+        TokenTree::Group(group) => contains_ident(&group.stream(), ident),
+        _ => false
+    })
+}
+
+/// SplitMix64, which is good enough to generate test data and needs no dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn chance(&mut self, percent: u64) -> bool {
+        self.next() % 100 < percent
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+const NAMES: [&str; 6] = ["S", "Answer", "Foo", "r#type", "r#match", "Deep42"];
+const VISIBILITIES: [&str; 5] = ["", "pub", "pub(crate)", "pub(super)", "pub(in crate::a)"];
+const ATTRIBUTES: [&str; 6] = [
+    "#[doc = \"generated\"]",
+    "/// A doc comment",
+    "#[derive(Debug, Clone)]",
+    "#[allow(dead_code)]",
+    "#[cfg_attr(test, derive(Default))]",
+    "#[must_use]",
+];
+const FIELD_NAMES: [&str; 5] = ["x", "value", "r#type", "r#match", "_y"];
+const VARIANT_NAMES: [&str; 4] = ["A", "B", "r#Match", "Last"];
+
+/// The description of a generated item. All parts are kept as source snippets, so they can be removed one by
+/// one when shrinking. The tested attribute is inserted at 'tested_position' among the other attributes.
+#[derive(Clone, Debug)]
+struct ItemSpec {
+    kind: ItemKind,
+    attributes: Vec<String>,
+    tested_position: usize,
+    vis: String,
+    name: String,
+    generics: Vec<String>,
+    where_predicates: Vec<String>,
+    members: Vec<String>,
+}
+
+impl ItemSpec {
+    fn generate(rng: &mut Rng, kinds: &[ItemKind]) -> Self {
+        let kind = *rng.pick(kinds);
+        let lifetimes = ["'a", "'b"][..rng.below(3)].to_vec();
+        let type_params = ["T", "U"][..rng.below(3)].to_vec();
+        let const_param = rng.chance(20);
+
+        let mut generics = vec![];
+        for lifetime in &lifetimes {
+            match *lifetime == "'b" && rng.chance(50) {
+                true => generics.push("'b: 'a".to_string()),
+                false => generics.push(lifetime.to_string())
+            }
+        }
+        for param in &type_params {
+            let bounds = bounds(rng, param, &lifetimes, &type_params);
+            match bounds.is_empty() {
+                true => generics.push(param.to_string()),
+                false => generics.push(format!("{}: {}", param, bounds.join(" + ")))
+            }
+        }
+        if const_param {
+            generics.push("const N: usize".to_string());
+        }
+
+        let types = types(&lifetimes, &type_params, const_param);
+        let where_predicates = (0..rng.below(3))
+            .filter_map(|_| where_predicate(rng, &lifetimes, &type_params))
+            .collect();
+        let members = members(rng, kind, &types);
+        let attributes = (0..rng.below(4)).map(|_| rng.pick(&ATTRIBUTES).to_string()).collect::<Vec<_>>();
+
+        ItemSpec {
+            kind,
+            tested_position: rng.below(attributes.len() + 1),
+            attributes,
+            vis: rng.pick(&VISIBILITIES).to_string(),
+            name: rng.pick(&NAMES).to_string(),
+            generics,
+            where_predicates,
+            members,
+        }
+    }
+
+    fn ident(&self) -> Ident {
+        syn::parse_str(&self.name).expect("the generated names are valid identifiers")
+    }
+
+    fn to_item(&self, tested_attribute: Option<&str>) -> Item {
+        syn::parse2(self.to_tokens(tested_attribute))
+            .unwrap_or_else(|e| panic!("The generated item does not parse: {}\n{}", e, self.to_source(tested_attribute)))
+    }
+
+    fn to_tokens(&self, tested_attribute: Option<&str>) -> TokenStream {
+        self.to_source(tested_attribute)
+            .parse()
+            .unwrap_or_else(|e| panic!("The generated item is not lexable: {}", e))
+    }
+
+    fn to_source(&self, tested_attribute: Option<&str>) -> String {
+        let mut attributes = self.attributes.clone();
+        if let Some(tested_attribute) = tested_attribute {
+            attributes.insert(self.tested_position.min(attributes.len()), tested_attribute.to_string());
+        }
+
+        let generics = match self.generics.is_empty() {
+            true => String::new(),
+            false => format!("<{}>", self.generics.join(", "))
+        };
+        let where_clause = match self.where_predicates.is_empty() {
+            true => String::new(),
+            false => format!(" where {}", self.where_predicates.join(", "))
+        };
+        let members = self.members.join(", ");
+        let header = format!("{}\n{} ", attributes.join("\n"), self.vis);
+
+        match self.kind {
+            ItemKind::Struct => format!("{}struct {}{}{} {{ {} }}", header, self.name, generics, where_clause, members),
+            ItemKind::TupleStruct => format!("{}struct {}{}({}){};", header, self.name, generics, members, where_clause),
+            ItemKind::UnitStruct => format!("{}struct {}{}{};", header, self.name, generics, where_clause),
+            ItemKind::Enum => format!("{}enum {}{}{} {{ {} }}", header, self.name, generics, where_clause, members),
+            ItemKind::Fn => format!("{}fn {}{}({}){} {{}}", header, self.name, generics, members, where_clause),
+            ItemKind::Trait => format!("{}trait {}{}{} {{ {} }}", header, self.name, generics, where_clause, self.members.join(" ")),
+        }
+    }
+
+    /// Smaller variants of the item, each with one part removed or simplified.
+    fn shrink_candidates(&self) -> Vec<ItemSpec> {
+        let mut candidates = vec![];
+
+        for index in 0..self.attributes.len() {
+            let mut candidate = self.clone();
+            candidate.attributes.remove(index);
+            candidates.push(candidate);
+        }
+        for index in 0..self.members.len() {
+            let mut candidate = self.clone();
+            candidate.members.remove(index);
+            candidates.push(candidate);
+        }
+        for index in 0..self.where_predicates.len() {
+            let mut candidate = self.clone();
+            candidate.where_predicates.remove(index);
+            candidates.push(candidate);
+        }
+        for index in 0..self.generics.len() {
+            // everything using the removed parameter is removed as well
+            let name = param_name(&self.generics[index]);
+            let mut candidate = self.clone();
+            candidate.generics.remove(index);
+            candidate.generics.retain(|g| !mentions(g, &name));
+            candidate.where_predicates.retain(|p| !mentions(p, &name));
+            candidate.members.retain(|m| !mentions(m, &name));
+            candidates.push(candidate);
+        }
+        if !self.vis.is_empty() {
+            candidates.push(ItemSpec { vis: String::new(), ..self.clone() });
+        }
+        if self.name != "S" {
+            candidates.push(ItemSpec { name: "S".to_string(), ..self.clone() });
+        }
+
+        candidates
+    }
+}
+
+fn bounds(rng: &mut Rng, param: &str, lifetimes: &[&str], type_params: &[&str]) -> Vec<String> {
+    let mut candidates = vec!["Clone".to_string(), "Default".to_string(), "std::fmt::Debug".to_string(), "Into<String>".to_string()];
+    candidates.extend(lifetimes.iter().map(|lifetime| lifetime.to_string()));
+    candidates.extend(type_params.iter().filter(|p| **p != param).map(|p| format!("Iterator<Item = {}>", p)));
+
+    (0..rng.below(3)).map(|_| rng.pick(&candidates).clone()).collect()
+}
+
+fn types(lifetimes: &[&str], type_params: &[&str], const_param: bool) -> Vec<String> {
+    let mut types = vec!["u8".to_string(), "String".to_string(), "Vec<bool>".to_string()];
+
+    for param in type_params {
+        types.push(param.to_string());
+        types.push(format!("Option<Box<{}>>", param));
+        types.extend(lifetimes.iter().map(|lifetime| format!("&{} {}", lifetime, param)));
+    }
+    types.extend(lifetimes.iter().map(|lifetime| format!("&{} str", lifetime)));
+    if const_param {
+        types.push("[u8; N]".to_string());
+    }
+
+    types
+}
+
+fn where_predicate(rng: &mut Rng, lifetimes: &[&str], type_params: &[&str]) -> Option<String> {
+    let mut candidates = vec![];
+
+    for param in type_params {
+        candidates.push(format!("{}: Default", param));
+        candidates.push(format!("Vec<{}>: Clone", param));
+    }
+    if lifetimes.len() == 2 {
+        candidates.push("'b: 'a".to_string());
+    }
+
+    match candidates.is_empty() {
+        true => None,
+        false => Some(rng.pick(&candidates).clone())
+    }
+}
+
+fn members(rng: &mut Rng, kind: ItemKind, types: &[String]) -> Vec<String> {
+    let count = match kind {
+        ItemKind::UnitStruct => 0,
+        _ => rng.below(4)
+    };
+
+    (0..count)
+        .map(|index| {
+            let ty = rng.pick(types).clone();
+            let vis = match rng.chance(30) {
+                true => "pub ",
+                false => ""
+            };
+
+            match kind {
+                ItemKind::Struct => format!("{}{}{}: {}", vis, FIELD_NAMES[index], index, ty),
+                ItemKind::TupleStruct => format!("{}{}", vis, ty),
+                ItemKind::Enum => match rng.below(3) {
+                    0 => format!("{}{}", VARIANT_NAMES[index], index),
+                    1 => format!("{}{}({})", VARIANT_NAMES[index], index, ty),
+                    _ => format!("{}{} {{ x: {} }}", VARIANT_NAMES[index], index, ty),
+                },
+                ItemKind::Fn => format!("{}{}: {}", FIELD_NAMES[index], index, ty),
+                ItemKind::Trait => format!("fn get_{}(&self) -> {};", index, ty),
+                ItemKind::UnitStruct => unreachable!("unit structs have no members"),
+            }
+        })
+        .collect()
+}
+
+/// The name a generic parameter declares, e.g. "'b" for "'b: 'a" and "N" for "const N: usize".
+fn param_name(param: &str) -> String {
+    let param = param.strip_prefix("const ").unwrap_or(param);
+    param.split(':').next().unwrap_or(param).trim().to_string()
+}
+
+/// Tells if the snippet uses the name as a whole word.
+fn mentions(snippet: &str, name: &str) -> bool {
+    snippet
+        .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '\''))
+        .any(|word| word == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::quote;
+    use syn::{Item, ItemStruct};
+    use crate::property::{ItemGenerator, ItemKind, PropertyTest};
+
+    #[test]
+    fn generated_items_parse() {
+        let mut generator = ItemGenerator::new(7);
+
+        for _ in 0..500 {
+            generator.next_item();
+        }
+    }
+
+    #[test]
+    fn failing_items_are_shrunk() {
+        let failure = PropertyTest::new(syn::parse_quote! { answer })
+            .kinds(&[ItemKind::Struct])
+            .run(|item| {
+                let item = match item {
+                    Item::Struct(item) => item,
+                    _ => unreachable!()
+                };
+                assert!(item.generics.where_clause.is_none(), "where clauses are not supported");
+                quote! {#item}
+            })
+            .unwrap_err();
+
+        let shrunk = syn::parse_str::<ItemStruct>(&failure.shrunk).unwrap();
+        assert_eq!(failure.violation.detail, "where clauses are not supported");
+        assert_eq!(shrunk.ident, "S");
+        assert_eq!(shrunk.attrs.len(), 1);
+        assert!(shrunk.fields.is_empty());
+        assert_eq!(shrunk.generics.where_clause.unwrap().predicates.len(), 1);
+    }
+}
@@ -0,0 +1,185 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use proc_macro2::TokenStream;
+use quote::quote;
+use crate::diff::pretty_print;
+use crate::temp_dir::TempDir;
+
+const CRATE_NAME: &str = "scratch";
+
+static SCRATCH_CRATES: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes the prelude and the expansion into the lib.rs of a temporary crate without dependencies and runs
+/// 'cargo check --offline' on it. Panics with the compiler errors next to the numbered lines of the checked
+/// code if it doesn't compile.
+pub fn assert_compiles(expansion: &TokenStream, prelude: &TokenStream) {
+    let code = pretty_print(&quote! { #prelude #expansion });
+
+    if let Err(errors) = check(&code) {
+        panic!("The expansion does not compile:\n{}\nThe checked src/lib.rs:\n{}", errors, numbered_lines(&code))
+    }
+}
+
+/// Writes the prelude, the expansion and a main function with the test body into a temporary crate without
+/// dependencies, builds it with 'cargo build --offline' and runs it. Panics with the output of the failed
+/// test body (or the compiler errors) next to the numbered lines of the code.
+pub fn assert_runs(expansion: &TokenStream, prelude: &TokenStream, test_body: &TokenStream) {
+    let code = pretty_print(&quote! {
+        #prelude
+        #expansion
+
+        fn main() {
+            #test_body
+        }
+    });
+
+    match run(&code) {
+        Ok(()) => {}
+        Err(Failure::Compilation(errors)) => {
+            panic!("The expansion does not compile:\n{}\nThe built src/main.rs:\n{}", errors, numbered_lines(&code))
+        }
+        Err(Failure::Execution(output)) => {
+            panic!("The test body failed:\n{}\nThe executed src/main.rs:\n{}", output, numbered_lines(&code))
+        }
+    }
+}
+
+enum Failure {
+    Compilation(String),
+    Execution(String),
+}
+
+fn check(code: &str) -> Result<(), String> {
+    ScratchCrate::new("lib.rs", code).cargo("check")
+}
+
+fn run(code: &str) -> Result<(), Failure> {
+    let scratch = ScratchCrate::new("main.rs", code);
+    scratch.cargo("build").map_err(Failure::Compilation)?;
+
+    let binary = scratch.binary();
+    let output = Command::new(&binary)
+        .output()
+        .unwrap_or_else(|e| panic!("Could not run {}: {}", binary.display(), e));
+
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(Failure::Execution(String::from_utf8_lossy(&output.stderr).into_owned()))
+    }
+}
+
+/// A temporary crate without dependencies. It is built into its own target directory inside the crate,
+/// so dropping it removes everything cargo created.
+struct ScratchCrate {
+    dir: TempDir,
+}
+
+impl ScratchCrate {
+    fn new(file_name: &str, code: &str) -> Self {
+        let scratch = ScratchCrate {
+            dir: TempDir::new(&format!("scratch_{}", SCRATCH_CRATES.fetch_add(1, Ordering::Relaxed))),
+        };
+
+        scratch.write(file_name, code)
+            .unwrap_or_else(|e| panic!("Could not create the crate {}: {}", scratch.dir.path().display(), e));
+        scratch
+    }
+
+    fn write(&self, file_name: &str, code: &str) -> std::io::Result<()> {
+        let src = self.dir.path().join("src");
+        fs::create_dir_all(&src)?;
+        // the empty workspace keeps cargo from looking for a workspace in the parent directories
+        fs::write(
+            self.dir.path().join("Cargo.toml"),
+            format!("[package]\nname = \"{}\"\nversion = \"0.0.0\"\nedition = \"2021\"\n\n[workspace]\n", CRATE_NAME),
+        )?;
+        fs::write(src.join(file_name), code)
+    }
+
+    fn target_dir(&self) -> PathBuf {
+        self.dir.path().join("target")
+    }
+
+    fn binary(&self) -> PathBuf {
+        self.target_dir().join("debug").join(format!("{}{}", CRATE_NAME, std::env::consts::EXE_SUFFIX))
+    }
+
+    /// Runs the cargo command and returns its error output if it fails.
+    fn cargo(&self, command: &str) -> Result<(), String> {
+        // cargo sets CARGO for tests, which is the cargo running them
+        let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+        let output = Command::new(cargo)
+            .args([command, "--offline", "--quiet", "--color", "never"])
+            .current_dir(self.dir.path())
+            .env("CARGO_TARGET_DIR", self.target_dir())
+            .output()
+            .unwrap_or_else(|e| panic!("Could not run cargo {}: {}", command, e));
+
+        match output.status.success() {
+            true => Ok(()),
+            false => Err(String::from_utf8_lossy(&output.stderr).into_owned())
+        }
+    }
+}
+
+fn numbered_lines(code: &str) -> String {
+    code.lines()
+        .enumerate()
+        .map(|(index, line)| format!("{:>4} | {}\n", index + 1, line))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::quote;
+    use crate::scratch::{assert_compiles, assert_runs, check, run, Failure, ScratchCrate};
+    use crate::diff::pretty_print;
+
+    #[test]
+    fn valid_expansion_compiles() {
+        assert_compiles(
+            &quote! { impl Answer for S { fn get_answer() -> usize { 42 } } },
+            &quote! { pub struct S; pub trait Answer { fn get_answer() -> usize; } },
+        )
+    }
+
+    #[test]
+    fn checked_crate_is_removed() {
+        let scratch = ScratchCrate::new("lib.rs", "pub struct S;");
+        scratch.cargo("check").unwrap();
+        let dir = scratch.dir.path().to_path_buf();
+        assert!(scratch.target_dir().exists());
+
+        drop(scratch);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn type_errors_are_reported() {
+        let errors = check(&pretty_print(&quote! { pub fn get_answer() -> usize { "42" } })).unwrap_err();
+
+        assert!(errors.contains("error[E0308]: mismatched types"), "{}", errors);
+        assert!(errors.contains("src/lib.rs:2:5"), "{}", errors);
+    }
+
+    #[test]
+    fn test_body_is_run() {
+        assert_runs(
+            &quote! { impl S { fn get_answer() -> usize { 42 } } },
+            &quote! { struct S; },
+            &quote! { assert_eq!(S::get_answer(), 42); },
+        )
+    }
+
+    #[test]
+    fn failing_test_body_is_reported() {
+        let code = pretty_print(&quote! { fn main() { assert_eq!(41, 42, "not the answer"); } });
+
+        match run(&code) {
+            Err(Failure::Execution(output)) => assert!(output.contains("not the answer"), "{}", output),
+            _ => panic!("the test body should fail")
+        }
+    }
+}
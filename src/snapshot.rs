@@ -0,0 +1,111 @@
+use std::fs;
+use std::path::Path;
+use proc_macro2::TokenStream;
+use crate::diff::{pretty_print, snapshot_report};
+
+/// If this environment variable is set to 1, snapshot tests store the current expansions instead of
+/// comparing them with the stored snapshots.
+pub const BLESS_VARIABLE: &str = "MACRO_TEST_BLESS";
+
+/// Compares the pretty-printed expansion with the snapshot '<snapshot_dir>/<name>.snap'. The name defaults
+/// to the name of the running test, with '::' replaced by '__'.
+/// If the snapshot is missing or differs, the expansion is written to '<name>.snap.new' and the test fails,
+/// so new snapshots have to be reviewed before they are accepted. With MACRO_TEST_BLESS=1 the snapshot
+/// is overwritten instead.
+pub fn assert_snapshot(expansion: &TokenStream, snapshot_dir: &Path, name: Option<&str>) {
+    let bless = std::env::var(BLESS_VARIABLE).map(|value| value == "1").unwrap_or(false);
+
+    if let Err(report) = check_snapshot(expansion, snapshot_dir, &snapshot_name(name), bless) {
+        panic!("{}", report)
+    }
+}
+
+fn snapshot_name(name: Option<&str>) -> String {
+    match name {
+        Some(name) => name.to_string(),
+        // the test harness runs every test in a thread named after the test
+        None => match std::thread::current().name() {
+            Some(test_name) if test_name != "main" => test_name.replace("::", "__"),
+            _ => panic!("Could not determine the name of the running test, name the snapshot with 'snapshot: \"name\"'")
+        }
+    }
+}
+
+fn check_snapshot(expansion: &TokenStream, snapshot_dir: &Path, name: &str, bless: bool) -> Result<(), String> {
+    let snapshot = snapshot_dir.join(format!("{}.snap", name));
+    let pending = snapshot_dir.join(format!("{}.snap.new", name));
+    let actual = snapshot_content(expansion);
+    let stored = fs::read_to_string(&snapshot).ok().map(|content| content.replace("\r\n", "\n"));
+
+    if stored.as_deref() == Some(actual.as_str()) {
+        // a pending snapshot of an earlier run is outdated
+        let _ = fs::remove_file(&pending);
+        return Ok(());
+    }
+
+    if bless {
+        write_snapshot(&snapshot, &actual);
+        let _ = fs::remove_file(&pending);
+        return Ok(());
+    }
+
+    write_snapshot(&pending, &actual);
+    match stored {
+        Some(stored) => Err(snapshot_report(&actual, &stored, &snapshot, &pending)),
+        None => Err(format!(
+            "There is no snapshot {} yet\nThe new snapshot was written to {}, rename it or rerun with {}=1 to accept it\n{}",
+            snapshot.display(),
+            pending.display(),
+            BLESS_VARIABLE,
+            actual
+        ))
+    }
+}
+
+fn snapshot_content(expansion: &TokenStream) -> String {
+    let content = pretty_print(expansion);
+    match content.ends_with('\n') {
+        true => content,
+        false => content + "\n"
+    }
+}
+
+fn write_snapshot(path: &Path, content: &str) {
+    path.parent()
+        .map(fs::create_dir_all)
+        .transpose()
+        .and_then(|_| fs::write(path, content))
+        .unwrap_or_else(|e| panic!("Could not write the snapshot {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::quote;
+    use crate::snapshot::check_snapshot;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn new_snapshots_are_pending() {
+        let temp_dir = TempDir::new("new_snapshots_are_pending");
+        let dir = temp_dir.path();
+        let report = check_snapshot(&quote! { struct S; }, dir, "new", false).unwrap_err();
+
+        assert!(report.starts_with("There is no snapshot"));
+        assert!(!dir.join("new.snap").exists());
+        assert_eq!(std::fs::read_to_string(dir.join("new.snap.new")).unwrap(), "struct S;\n");
+    }
+
+    #[test]
+    fn blessed_snapshots_are_compared() {
+        let temp_dir = TempDir::new("blessed_snapshots_are_compared");
+        let dir = temp_dir.path();
+
+        assert!(check_snapshot(&quote! { struct S; }, dir, "blessed", true).is_ok());
+        assert!(check_snapshot(&quote! { struct S; }, dir, "blessed", false).is_ok());
+
+        let report = check_snapshot(&quote! { struct T; }, dir, "blessed", false).unwrap_err();
+        assert!(report.ends_with("-struct S;\n+struct T;\n"));
+        assert_eq!(std::fs::read_to_string(dir.join("blessed.snap")).unwrap(), "struct S;\n");
+        assert_eq!(std::fs::read_to_string(dir.join("blessed.snap.new")).unwrap(), "struct T;\n");
+    }
+}
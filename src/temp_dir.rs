@@ -0,0 +1,43 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A directory in the temp dir of the system, which is deleted with its content when it's dropped. Its name
+/// contains the process id, so test runs in parallel don't share it. Used for scratch crates and in tests.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// The directory isn't created, but a leftover of a killed process with the same id is removed.
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("macro_test_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn directory_is_removed_on_drop() {
+        let dir = TempDir::new("directory_is_removed_on_drop");
+        std::fs::create_dir_all(dir.path().join("nested")).unwrap();
+        std::fs::write(dir.path().join("nested").join("file"), "content").unwrap();
+        let path = dir.path().to_path_buf();
+
+        drop(dir);
+        assert!(!path.exists());
+    }
+}